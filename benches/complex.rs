@@ -1,7 +1,7 @@
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
-use protobuf_conversion::heavy;
-use protobuf_conversion::light;
-use protobuf_conversion::reflect::Reflect;
+use tobu_conversion::heavy;
+use tobu_conversion::light;
+use tobu_conversion::reflect::{Reflect, ReflectBorrow};
 
 fn fill_heavy_complex(m: &mut heavy::complex::Complex) {
     m.optional_enum = Some(heavy::complex::ComplexEnum::Ten);
@@ -111,6 +111,22 @@ fn reflect(c: &mut Criterion) {
     group.finish();
 }
 
+// Light types lend their message. Heavy types convert a copy, so they cost about as much as
+// complex/reflect on a clone.
+fn reflect_ref(c: &mut Criterion) {
+    let mut group = c.benchmark_group("complex/reflect_ref");
+
+    let mut m = heavy::complex::Complex::new();
+    fill_heavy_complex(&mut m);
+    group.bench_function("heavy", |b| b.iter(|| m.reflect_ref()));
+
+    let mut m = light::complex::Complex::new();
+    fill_light_complex(&mut m);
+    group.bench_function("light", |b| b.iter(|| m.reflect_ref()));
+
+    group.finish();
+}
+
 fn absorb(c: &mut Criterion) {
     let mut group = c.benchmark_group("complex/absorb");
 
@@ -131,5 +147,54 @@ fn absorb(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, new, access, mutate, reflect, absorb);
+fn convert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("complex/convert");
+
+    let mut m = heavy::complex::Complex::new();
+    fill_heavy_complex(&mut m);
+    group.bench_function("heavy_to_light/from", |b| {
+        b.iter_batched(
+            || m.clone(),
+            light::complex::Complex::from,
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("heavy_to_light/absorb", |b| {
+        b.iter_batched(
+            || m.clone(),
+            |m| m.reflect().retarget::<light::complex::Complex>()?.absorb(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    let mut m = light::complex::Complex::new();
+    fill_light_complex(&mut m);
+    group.bench_function("light_to_heavy/from", |b| {
+        b.iter_batched(
+            || m.clone(),
+            heavy::complex::Complex::from,
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("light_to_heavy/absorb", |b| {
+        b.iter_batched(
+            || m.clone(),
+            |m| m.reflect().retarget::<heavy::complex::Complex>()?.absorb(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    new,
+    access,
+    mutate,
+    reflect,
+    reflect_ref,
+    absorb,
+    convert
+);
 criterion_main!(benches);
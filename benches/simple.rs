@@ -1,7 +1,7 @@
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
-use protobuf_conversion::heavy;
-use protobuf_conversion::light;
-use protobuf_conversion::reflect::Reflect;
+use tobu_conversion::heavy;
+use tobu_conversion::light;
+use tobu_conversion::reflect::Reflect;
 
 fn fill_heavy_simple(m: &mut heavy::simple::Simple) {
     m.simple_bool = true;
@@ -0,0 +1,258 @@
+use proc_macro2::{Ident, Span};
+use syn::{
+    meta::ParseNestedMeta, spanned::Spanned, Attribute, Error, Lit, LitInt, LitStr, Result, Token,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProtoType {
+    Double,
+    Float,
+    Int64,
+    UInt64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Message,
+    Bytes,
+    UInt32,
+    Enum,
+    SFixed64,
+    SFixed32,
+    SInt32,
+    SInt64,
+}
+
+impl ProtoType {
+    pub fn parse(lit: &LitStr) -> Result<Self> {
+        Ok(match lit.value().as_str() {
+            "double" => ProtoType::Double,
+            "float" => ProtoType::Float,
+            "int64" => ProtoType::Int64,
+            "uint64" => ProtoType::UInt64,
+            "int32" => ProtoType::Int32,
+            "fixed64" => ProtoType::Fixed64,
+            "fixed32" => ProtoType::Fixed32,
+            "bool" => ProtoType::Bool,
+            "string" => ProtoType::String,
+            "message" => ProtoType::Message,
+            "bytes" => ProtoType::Bytes,
+            "uint32" => ProtoType::UInt32,
+            "enum" => ProtoType::Enum,
+            "sfixed64" => ProtoType::SFixed64,
+            "sfixed32" => ProtoType::SFixed32,
+            "sint32" => ProtoType::SInt32,
+            "sint64" => ProtoType::SInt64,
+            _ => return Err(Error::new(lit.span(), "unknown protobuf type")),
+        })
+    }
+
+    // Name of the descriptor::Type variant.
+    pub fn descriptor_ident(self) -> Ident {
+        let name = match self {
+            ProtoType::Double => "Double",
+            ProtoType::Float => "Float",
+            ProtoType::Int64 => "Int64",
+            ProtoType::UInt64 => "UInt64",
+            ProtoType::Int32 => "Int32",
+            ProtoType::Fixed64 => "Fixed64",
+            ProtoType::Fixed32 => "Fixed32",
+            ProtoType::Bool => "Bool",
+            ProtoType::String => "String",
+            ProtoType::Message => "Message",
+            ProtoType::Bytes => "Bytes",
+            ProtoType::UInt32 => "UInt32",
+            ProtoType::Enum => "Enum",
+            ProtoType::SFixed64 => "SFixed64",
+            ProtoType::SFixed32 => "SFixed32",
+            ProtoType::SInt32 => "SInt32",
+            ProtoType::SInt64 => "SInt64",
+        };
+        Ident::new(name, Span::call_site())
+    }
+
+    // Name of the value::Value variant.
+    pub fn value_ident(self) -> Ident {
+        let name = match self {
+            ProtoType::Double => "F64",
+            ProtoType::Float => "F32",
+            ProtoType::Int64 | ProtoType::SFixed64 | ProtoType::SInt64 => "I64",
+            ProtoType::UInt64 | ProtoType::Fixed64 => "U64",
+            ProtoType::Int32 | ProtoType::SFixed32 | ProtoType::SInt32 => "I32",
+            ProtoType::UInt32 | ProtoType::Fixed32 => "U32",
+            ProtoType::Bool => "Bool",
+            ProtoType::String => "String",
+            ProtoType::Message => "Message",
+            ProtoType::Bytes => "Bytes",
+            ProtoType::Enum => "Enum",
+        };
+        Ident::new(name, Span::call_site())
+    }
+
+    // Name of the value::Key variant, if the type may be used as a map key.
+    pub fn key_ident(self) -> Option<Ident> {
+        let name = match self {
+            ProtoType::Bool => "Bool",
+            ProtoType::String => "String",
+            ProtoType::Int32 | ProtoType::SFixed32 | ProtoType::SInt32 => "I32",
+            ProtoType::Int64 | ProtoType::SFixed64 | ProtoType::SInt64 => "I64",
+            ProtoType::UInt32 | ProtoType::Fixed32 => "U32",
+            ProtoType::UInt64 | ProtoType::Fixed64 => "U64",
+            _ => return None,
+        };
+        Some(Ident::new(name, Span::call_site()))
+    }
+}
+
+#[derive(Default)]
+pub struct MessageAttr {
+    pub name: Option<LitStr>,
+}
+
+impl MessageAttr {
+    pub fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut out = MessageAttr::default();
+        for attr in attrs.iter().filter(|a| a.path().is_ident("proto")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    out.name = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown proto message attribute"))
+                }
+            })?;
+        }
+        Ok(out)
+    }
+}
+
+pub struct FieldAttr {
+    pub number: u32,
+    pub ty: ProtoType,
+    pub key: Option<ProtoType>,
+    pub required: bool,
+    pub constraints: Vec<(Span, Constraint)>,
+}
+
+// Mirrors descriptor::Constraint.
+pub enum Constraint {
+    MinLen(usize),
+    MaxLen(usize),
+    Pattern(String),
+    Min(Number),
+    Max(Number),
+    MinItems(usize),
+    MaxItems(usize),
+    Unique,
+    RequiredKey(String),
+    DefinedOnly,
+}
+
+pub enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl Number {
+    // Accepts an integer or float literal with an optional leading minus sign.
+    fn parse(meta: &ParseNestedMeta) -> Result<Self> {
+        let input = meta.value()?;
+        let negative = input.parse::<Option<Token![-]>>()?.is_some();
+        let sign = if negative { -1 } else { 1 };
+        match input.parse()? {
+            Lit::Int(lit) => Ok(Number::Int(sign as i128 * lit.base10_parse::<i128>()?)),
+            Lit::Float(lit) => Ok(Number::Float(sign as f64 * lit.base10_parse::<f64>()?)),
+            lit => Err(Error::new(lit.span(), "expected a number")),
+        }
+    }
+}
+
+fn parse_usize(meta: &ParseNestedMeta) -> Result<usize> {
+    meta.value()?.parse::<LitInt>()?.base10_parse()
+}
+
+pub enum FieldKind {
+    Proto(FieldAttr),
+    UnknownFields,
+}
+
+impl FieldKind {
+    pub fn parse(span: Span, attrs: &[Attribute]) -> Result<Self> {
+        let mut number = None;
+        let mut ty = None;
+        let mut key = None;
+        let mut required = false;
+        let mut constraints = Vec::new();
+        let mut unknown_fields = false;
+        for attr in attrs.iter().filter(|a| a.path().is_ident("proto")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("unknown_fields") {
+                    unknown_fields = true;
+                } else if meta.path.is_ident("required") {
+                    required = true;
+                } else if meta.path.is_ident("number") {
+                    let lit: LitInt = meta.value()?.parse()?;
+                    number = Some(lit.base10_parse()?);
+                } else if meta.path.is_ident("ty") {
+                    ty = Some(ProtoType::parse(&meta.value()?.parse()?)?);
+                } else if meta.path.is_ident("key") {
+                    key = Some(ProtoType::parse(&meta.value()?.parse()?)?);
+                } else if let Some(constraint) = parse_constraint(&meta)? {
+                    constraints.push((meta.path.span(), constraint));
+                } else {
+                    return Err(meta.error("unknown proto field attribute"));
+                }
+                Ok(())
+            })?;
+        }
+
+        if unknown_fields {
+            return match (number, ty, key, required, constraints.is_empty()) {
+                (None, None, None, false, true) => Ok(FieldKind::UnknownFields),
+                _ => Err(Error::new(
+                    span,
+                    "#[proto(unknown_fields)] cannot be combined with other attributes",
+                )),
+            };
+        }
+
+        Ok(FieldKind::Proto(FieldAttr {
+            number: number.ok_or_else(|| Error::new(span, "missing #[proto(number = ..)]"))?,
+            ty: ty.ok_or_else(|| Error::new(span, "missing #[proto(ty = \"..\")]"))?,
+            key,
+            required,
+            constraints,
+        }))
+    }
+}
+
+fn parse_constraint(meta: &ParseNestedMeta) -> Result<Option<Constraint>> {
+    let path = &meta.path;
+    Ok(Some(if path.is_ident("min_len") {
+        Constraint::MinLen(parse_usize(meta)?)
+    } else if path.is_ident("max_len") {
+        Constraint::MaxLen(parse_usize(meta)?)
+    } else if path.is_ident("pattern") {
+        let lit: LitStr = meta.value()?.parse()?;
+        regex::Regex::new(&lit.value())
+            .map_err(|e| Error::new(lit.span(), format!("invalid pattern: {}", e)))?;
+        Constraint::Pattern(lit.value())
+    } else if path.is_ident("min") {
+        Constraint::Min(Number::parse(meta)?)
+    } else if path.is_ident("max") {
+        Constraint::Max(Number::parse(meta)?)
+    } else if path.is_ident("min_items") {
+        Constraint::MinItems(parse_usize(meta)?)
+    } else if path.is_ident("max_items") {
+        Constraint::MaxItems(parse_usize(meta)?)
+    } else if path.is_ident("unique") {
+        Constraint::Unique
+    } else if path.is_ident("required_key") {
+        Constraint::RequiredKey(meta.value()?.parse::<LitStr>()?.value())
+    } else if path.is_ident("defined_only") {
+        Constraint::DefinedOnly
+    } else {
+        return Ok(None);
+    }))
+}
@@ -0,0 +1,500 @@
+use proc_macro2::{Literal, TokenStream};
+use quote::{quote, ToTokens};
+use syn::{
+    spanned::Spanned, Data, DeriveInput, Error, Fields, GenericArgument, Ident, PathArguments,
+    Result, Type,
+};
+
+use crate::attr::{Constraint, FieldAttr, FieldKind, MessageAttr, Number, ProtoType};
+
+enum Shape<'a> {
+    Singular(&'a Type),
+    Optional(&'a Type),
+    Repeated(&'a Type),
+    Map(&'a Type),
+}
+
+struct Field<'a> {
+    ident: &'a Ident,
+    attr: FieldAttr,
+    shape: Shape<'a>,
+}
+
+pub fn derive_reflect(input: DeriveInput) -> Result<TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "#[derive(Reflect)] does not support generic messages",
+        ));
+    }
+
+    let named = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(named) => &named.named,
+            _ => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "#[derive(Reflect)] requires named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "#[derive(Reflect)] can only be used on structs",
+            ))
+        }
+    };
+
+    let message_attr = MessageAttr::parse(&input.attrs)?;
+    let mut unknown_fields = None;
+    let mut fields = Vec::new();
+    for f in named {
+        match FieldKind::parse(f.span(), &f.attrs)? {
+            FieldKind::Proto(attr) => fields.push((f, attr)),
+            FieldKind::UnknownFields if unknown_fields.is_none() => {
+                unknown_fields = f.ident.as_ref()
+            }
+            FieldKind::UnknownFields => {
+                return Err(Error::new(
+                    f.span(),
+                    "only one field may be marked #[proto(unknown_fields)]",
+                ))
+            }
+        }
+    }
+
+    let fields = fields
+        .into_iter()
+        .map(|(f, attr)| {
+            let shape = shape(&f.ty, &attr)?;
+            if attr.required && matches!(shape, Shape::Repeated(_) | Shape::Map(..)) {
+                return Err(Error::new(
+                    f.span(),
+                    "repeated and map fields cannot be #[proto(required)]",
+                ));
+            }
+            for (span, constraint) in &attr.constraints {
+                if !constraint_applies(constraint, attr.ty, &shape) {
+                    return Err(Error::new(*span, "constraint does not apply to this field"));
+                }
+            }
+            Ok(Field {
+                ident: f.ident.as_ref().unwrap(),
+                attr,
+                shape,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let ident = &input.ident;
+    let name = match message_attr.name {
+        Some(name) => name.into_token_stream(),
+        None => ident.to_string().into_token_stream(),
+    };
+
+    let into_fields = fields.iter().map(into_field);
+    let from_fields = fields.iter().enumerate().map(|(i, f)| from_field(i, f));
+    let descriptor_fields = fields.iter().map(descriptor_field);
+    let len = fields.len();
+
+    let (into_unknown, from_unknown) = match unknown_fields {
+        Some(ident) => (quote!(m.#ident), quote!(#ident: m.unknown_fields,)),
+        None => (quote!(::std::vec::Vec::new()), quote!()),
+    };
+    let retain_known_enums = if fields.iter().any(|f| f.attr.ty == ProtoType::Enum) {
+        quote! {
+            let mut m = m;
+            ::tobu_conversion::codec::retain_known_enums(
+                &mut m,
+                <Self as ::tobu_conversion::message::ProtoMessage>::descriptor(),
+            );
+        }
+    } else {
+        quote!()
+    };
+
+    Ok(quote! {
+        impl ::tobu_conversion::message::ProtoMessage for #ident {
+            fn descriptor() -> &'static ::tobu_conversion::descriptor::MessageDescriptor {
+                static DESCRIPTOR: ::tobu_conversion::descriptor::MessageDescriptor =
+                    ::tobu_conversion::descriptor::MessageDescriptor {
+                        name: #name,
+                        fields: &[#(#descriptor_fields),*],
+                    };
+                &DESCRIPTOR
+            }
+
+            fn as_message(&self) -> ::std::borrow::Cow<'_, ::tobu_conversion::value::Message> {
+                ::std::borrow::Cow::Owned(::core::convert::Into::into(::core::clone::Clone::clone(self)))
+            }
+        }
+
+        impl ::tobu_conversion::reflect::ReflectBorrow for #ident {}
+
+        impl ::core::convert::From<#ident> for ::tobu_conversion::value::Message {
+            fn from(m: #ident) -> Self {
+                ::tobu_conversion::value::Message {
+                    fields: ::std::vec![#(#into_fields),*],
+                    unknown_fields: #into_unknown,
+                }
+            }
+        }
+
+        impl ::core::convert::TryFrom<::tobu_conversion::value::Message> for #ident {
+            type Error = ::tobu_conversion::error::AbsorbError;
+
+            fn try_from(m: ::tobu_conversion::value::Message) -> ::core::result::Result<Self, Self::Error> {
+                #retain_known_enums
+                if m.fields.len() != #len {
+                    return ::core::result::Result::Err(
+                        ::tobu_conversion::error::AbsorbError::invalid_length(#len, m.fields.len()),
+                    );
+                }
+
+                let mut fields = m.fields.into_iter();
+                ::core::result::Result::Ok(#ident {
+                    #(#from_fields,)*
+                    #from_unknown
+                })
+            }
+        }
+    })
+}
+
+// Map fields are only recognized by their #[proto(key = "..")] attribute, so any map type
+// with two type arguments can back one.
+fn shape<'a>(ty: &'a Type, attr: &FieldAttr) -> Result<Shape<'a>> {
+    if let Some(key) = attr.key {
+        if key.key_ident().is_none() {
+            return Err(Error::new(ty.span(), "invalid map key type"));
+        }
+        return match generic_args(ty) {
+            Some((_, args)) if args.len() == 2 => Ok(Shape::Map(args[1])),
+            _ => Err(Error::new(
+                ty.span(),
+                "#[proto(key = \"..\")] requires a map type with key and value type arguments",
+            )),
+        };
+    }
+
+    Ok(match generic_args(ty) {
+        Some((outer, args)) if outer == "Option" && args.len() == 1 => Shape::Optional(args[0]),
+        Some((outer, args)) if outer == "Vec" && args.len() == 1 => {
+            if attr.ty == ProtoType::Bytes && is_ident(args[0], "u8") {
+                Shape::Singular(ty)
+            } else {
+                Shape::Repeated(args[0])
+            }
+        }
+        _ => Shape::Singular(ty),
+    })
+}
+
+fn generic_args(ty: &Type) -> Option<(String, Vec<&Type>)> {
+    let segment = match ty {
+        Type::Path(p) if p.qself.is_none() => p.path.segments.last()?,
+        _ => return None,
+    };
+    let args = match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .filter_map(|a| match a {
+                GenericArgument::Type(t) => Some(t),
+                _ => None,
+            })
+            .collect(),
+        _ => return None,
+    };
+    Some((segment.ident.to_string(), args))
+}
+
+fn is_ident(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(p) => p.qself.is_none() && p.path.is_ident(name),
+        _ => false,
+    }
+}
+
+fn map_key(field: &Field) -> ProtoType {
+    field.attr.key.unwrap()
+}
+
+// Converts a bound `v` from the Rust type into its Value payload.
+fn into_payload(proto: ProtoType) -> TokenStream {
+    match proto {
+        ProtoType::Enum => quote!(::tobu_conversion::value::Enum::from(v)),
+        ProtoType::Message => quote!(::tobu_conversion::value::Message::from(v)),
+        _ => quote!(v),
+    }
+}
+
+fn is_fallible(proto: ProtoType) -> bool {
+    proto == ProtoType::Enum || proto == ProtoType::Message
+}
+
+// Converts a bound `v` from its Value payload back into the Rust type. `locate` places an
+// error at the position of `v` in the message.
+fn from_payload(proto: ProtoType, ty: &Type, locate: TokenStream) -> TokenStream {
+    if is_fallible(proto) {
+        quote! {
+            <#ty as ::core::convert::TryFrom<_>>::try_from(v)
+                .map_err(|e| {
+                    let e = ::core::convert::Into::<::tobu_conversion::error::AbsorbError>::into(e);
+                    #locate
+                })?
+        }
+    } else {
+        quote!(v)
+    }
+}
+
+fn into_field(field: &Field) -> TokenStream {
+    let ident = field.ident;
+    let variant = field.attr.ty.value_ident();
+    let payload = into_payload(field.attr.ty);
+    let value = quote!(::tobu_conversion::value::Value);
+    let rule = quote!(::tobu_conversion::value::Rule);
+
+    match field.shape {
+        Shape::Singular(_) => quote! {
+            ::core::option::Option::Some({
+                let v = m.#ident;
+                #value::#variant(#rule::Singular(#payload))
+            })
+        },
+        Shape::Optional(_) => quote! {
+            m.#ident.map(|v| #value::#variant(#rule::Singular(#payload)))
+        },
+        Shape::Repeated(_) => quote! {
+            ::core::option::Option::Some(#value::#variant(#rule::Repeated(
+                m.#ident.into_iter().map(|v| #payload).collect(),
+            )))
+        },
+        Shape::Map(_) => {
+            let key = map_key(field).key_ident().unwrap();
+            quote! {
+                ::core::option::Option::Some(#value::#variant(#rule::Map(
+                    ::tobu_conversion::value::Key::#key(
+                        m.#ident.into_iter().map(|(k, v)| (k, #payload)).collect(),
+                    ),
+                )))
+            }
+        }
+    }
+}
+
+fn from_field(index: usize, field: &Field) -> TokenStream {
+    let ident = field.ident;
+    let name = ident.to_string();
+    let variant = field.attr.ty.value_ident();
+    let value = quote!(::tobu_conversion::value::Value);
+    let rule = quote!(::tobu_conversion::value::Rule);
+    let error = quote!(::tobu_conversion::error::AbsorbError);
+    let descriptor = quote! {
+        &<Self as ::tobu_conversion::message::ProtoMessage>::descriptor().fields[#index]
+    };
+
+    let body = match field.shape {
+        Shape::Singular(ty) => {
+            let payload = from_payload(field.attr.ty, ty, quote!(e.at(#name)));
+            quote! {
+                ::core::option::Option::Some(#value::#variant(#rule::Singular(v))) => #payload,
+                ::core::option::Option::Some(v) => {
+                    return ::core::result::Result::Err(#error::invalid_type(#descriptor, &v))
+                }
+                ::core::option::Option::None => ::core::default::Default::default(),
+            }
+        }
+        Shape::Optional(ty) => {
+            let payload = from_payload(field.attr.ty, ty, quote!(e.at(#name)));
+            quote! {
+                ::core::option::Option::Some(#value::#variant(#rule::Singular(v))) => {
+                    ::core::option::Option::Some(#payload)
+                }
+                ::core::option::Option::Some(v) => {
+                    return ::core::result::Result::Err(#error::invalid_type(#descriptor, &v))
+                }
+                ::core::option::Option::None => ::core::option::Option::None,
+            }
+        }
+        Shape::Repeated(ty) => {
+            let payload = from_payload(field.attr.ty, ty, quote!(e.at_key(#name, i)));
+            let convert = if is_fallible(field.attr.ty) {
+                quote! {
+                    v.into_iter()
+                        .enumerate()
+                        .map(|(i, v)| ::core::result::Result::Ok(#payload))
+                        .collect::<::core::result::Result<_, #error>>()?
+                }
+            } else {
+                quote!(v)
+            };
+            quote! {
+                ::core::option::Option::Some(#value::#variant(#rule::Repeated(v))) => #convert,
+                ::core::option::Option::Some(v) => {
+                    return ::core::result::Result::Err(#error::invalid_type(#descriptor, &v))
+                }
+                // An unset repeated or map field is empty.
+                ::core::option::Option::None => ::core::default::Default::default(),
+            }
+        }
+        Shape::Map(ty) => {
+            let key = map_key(field).key_ident().unwrap();
+            let payload = from_payload(field.attr.ty, ty, quote!(e.at_key(#name, &k)));
+            let convert = if is_fallible(field.attr.ty) {
+                quote! {
+                    v.into_iter()
+                        .map(|(k, v)| {
+                            let v = #payload;
+                            ::core::result::Result::Ok((k, v))
+                        })
+                        .collect::<::core::result::Result<_, #error>>()?
+                }
+            } else {
+                quote!(v.into_iter().collect())
+            };
+            quote! {
+                ::core::option::Option::Some(#value::#variant(#rule::Map(
+                    ::tobu_conversion::value::Key::#key(v),
+                ))) => #convert,
+                ::core::option::Option::Some(v) => {
+                    return ::core::result::Result::Err(#error::invalid_type(#descriptor, &v))
+                }
+                // An unset repeated or map field is empty.
+                ::core::option::Option::None => ::core::default::Default::default(),
+            }
+        }
+    };
+
+    quote! {
+        #ident: match fields.next().unwrap() {
+            #body
+        }
+    }
+}
+
+fn descriptor_field(field: &Field) -> TokenStream {
+    let name = field.ident.to_string();
+    let number = field.attr.number;
+    let ty = field.attr.ty.descriptor_ident();
+    let descriptor = quote!(::tobu_conversion::descriptor);
+
+    let (label, presence, key, ty_path) = match field.shape {
+        Shape::Singular(ty) | Shape::Optional(ty) if field.attr.required => {
+            (quote!(Required), quote!(LegacyRequired), quote!(None), ty)
+        }
+        // Message fields always track presence, even when not wrapped in an Option.
+        Shape::Singular(ty) if field.attr.ty == ProtoType::Message => {
+            (quote!(Optional), quote!(Explicit), quote!(None), ty)
+        }
+        Shape::Singular(ty) => (quote!(Optional), quote!(Implicit), quote!(None), ty),
+        Shape::Optional(ty) => (quote!(Optional), quote!(Explicit), quote!(None), ty),
+        Shape::Repeated(ty) => (quote!(Repeated), quote!(Implicit), quote!(None), ty),
+        Shape::Map(ty) => {
+            let key = map_key(field).descriptor_ident();
+            (
+                quote!(Repeated),
+                quote!(Implicit),
+                quote!(Some(#descriptor::Type::#key)),
+                ty,
+            )
+        }
+    };
+    let message = match field.attr.ty {
+        ProtoType::Message => quote! {
+            Some(<#ty_path as ::tobu_conversion::message::ProtoMessage>::descriptor)
+        },
+        _ => quote!(None),
+    };
+    let enumeration = match field.attr.ty {
+        ProtoType::Enum => {
+            // Open enums are stored as EnumOrUnknown<E>; the descriptor belongs to E.
+            let ty_path = match generic_args(ty_path) {
+                Some((outer, args)) if outer == "EnumOrUnknown" && args.len() == 1 => args[0],
+                _ => ty_path,
+            };
+            quote! {
+                Some(<#ty_path as ::tobu_conversion::enumeration::ProtoEnum>::descriptor)
+            }
+        }
+        _ => quote!(None),
+    };
+
+    let constraints = field
+        .attr
+        .constraints
+        .iter()
+        .map(|(_, c)| constraint_tokens(c));
+
+    quote! {
+        #descriptor::FieldDescriptor {
+            name: #name,
+            number: #number,
+            ty: #descriptor::Type::#ty,
+            label: #descriptor::Label::#label,
+            presence: #descriptor::Presence::#presence,
+            key: ::core::option::Option::#key,
+            message: ::core::option::Option::#message,
+            enumeration: ::core::option::Option::#enumeration,
+            constraints: &[#(#constraints),*],
+        }
+    }
+}
+
+fn constraint_applies(constraint: &Constraint, ty: ProtoType, shape: &Shape) -> bool {
+    let numeric = !matches!(
+        ty,
+        ProtoType::Bool
+            | ProtoType::String
+            | ProtoType::Message
+            | ProtoType::Bytes
+            | ProtoType::Enum
+    );
+    match constraint {
+        Constraint::MinLen(_) | Constraint::MaxLen(_) => {
+            matches!(ty, ProtoType::String | ProtoType::Bytes)
+        }
+        Constraint::Pattern(_) => ty == ProtoType::String,
+        Constraint::Min(_) | Constraint::Max(_) => numeric,
+        Constraint::MinItems(_) | Constraint::MaxItems(_) => {
+            matches!(shape, Shape::Repeated(_) | Shape::Map(..))
+        }
+        Constraint::Unique => matches!(shape, Shape::Repeated(_)) && ty != ProtoType::Message,
+        Constraint::RequiredKey(_) => matches!(shape, Shape::Map(..)),
+        Constraint::DefinedOnly => ty == ProtoType::Enum,
+    }
+}
+
+fn constraint_tokens(constraint: &Constraint) -> TokenStream {
+    let descriptor = quote!(::tobu_conversion::descriptor);
+    let number = |n: &Number| match n {
+        Number::Int(n) => {
+            let n = Literal::i128_unsuffixed(*n);
+            quote!(#descriptor::Number::Int(#n))
+        }
+        Number::Float(n) => {
+            let n = Literal::f64_unsuffixed(*n);
+            quote!(#descriptor::Number::Float(#n))
+        }
+    };
+    match constraint {
+        Constraint::MinLen(n) => quote!(#descriptor::Constraint::MinLen(#n)),
+        Constraint::MaxLen(n) => quote!(#descriptor::Constraint::MaxLen(#n)),
+        Constraint::Pattern(p) => quote!(#descriptor::Constraint::Pattern(#p)),
+        Constraint::Min(n) => {
+            let n = number(n);
+            quote!(#descriptor::Constraint::Min(#n))
+        }
+        Constraint::Max(n) => {
+            let n = number(n);
+            quote!(#descriptor::Constraint::Max(#n))
+        }
+        Constraint::MinItems(n) => quote!(#descriptor::Constraint::MinItems(#n)),
+        Constraint::MaxItems(n) => quote!(#descriptor::Constraint::MaxItems(#n)),
+        Constraint::Unique => quote!(#descriptor::Constraint::Unique),
+        Constraint::RequiredKey(k) => quote!(#descriptor::Constraint::RequiredKey(#k)),
+        Constraint::DefinedOnly => quote!(#descriptor::Constraint::DefinedOnly),
+    }
+}
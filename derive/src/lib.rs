@@ -0,0 +1,13 @@
+mod attr;
+mod expand;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput};
+
+#[proc_macro_derive(Reflect, attributes(proto))]
+pub fn derive_reflect(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand::derive_reflect(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,1680 @@
+use std::{fmt::Display, mem};
+
+use crate::{
+    descriptor::{FieldDescriptor, Label, MessageDescriptor, Presence, Type},
+    error::{AbsorbError, DecodeError, DescriptorError, EncodeError},
+    value::{Enum, Key, KeyVariant, Map, Message, Rule, Value, Variant},
+    wire::{self, Reader, WireType},
+};
+
+pub const RECURSION_LIMIT: usize = 100;
+
+/// Computes the encoded size without encoding or allocating, for reserving buffers or enforcing
+/// size limits.
+pub fn encoded_len(m: &Message, d: &MessageDescriptor) -> Result<usize, EncodeError> {
+    message_len(m, d, &mut Sizes::default())
+}
+
+/// Encodes with the default options, writing map entries in iteration order.
+pub fn encode(m: &Message, d: &MessageDescriptor, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+    encode_with(m, d, &EncodeOptions::default(), buf)
+}
+
+/// Measures the message once up front, then writes it into a buffer reserved to fit. The sizes of
+/// nested messages are recorded while measuring and reused for their length prefixes.
+pub fn encode_with(
+    m: &Message,
+    d: &MessageDescriptor,
+    options: &EncodeOptions,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    let mut sizes = Sizes::recording(options);
+    buf.reserve(message_len(m, d, &mut sizes)?);
+    sizes.replay();
+    encode_message(m, d, &mut sizes, buf)
+}
+
+// Like encode_with, but writes the length of the message as a varint first, taken from the same
+// measurement.
+pub(crate) fn encode_length_delimited(
+    m: &Message,
+    d: &MessageDescriptor,
+    options: &EncodeOptions,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    let mut sizes = Sizes::recording(options);
+    let len = message_len(m, d, &mut sizes)?;
+    buf.reserve(wire::varint_len(len as u64) + len);
+    wire::encode_varint(len as u64, buf);
+    sizes.replay();
+    encode_message(m, d, &mut sizes, buf)
+}
+
+/// How encode_with writes a message. This only covers the binary format; the crate has no JSON
+/// or text format. Tests that compare encoded bytes can use `testing::encode`, which encodes
+/// deterministically.
+///
+/// ```
+/// use tobu_conversion::{
+///     codec::EncodeOptions,
+///     heavy::complex::{Complex, ComplexNested},
+///     message::ProtoMessage,
+/// };
+///
+/// let options = EncodeOptions {
+///     deterministic: true,
+/// };
+/// let (mut a, mut b) = (Complex::default(), Complex::default());
+/// for k in [3, 1, 2] {
+///     a.map_message.insert(k, ComplexNested::default());
+///     b.map_message.insert(4 - k, ComplexNested::default());
+/// }
+/// let (mut x, mut y) = (Vec::new(), Vec::new());
+/// a.encode_with(&options, &mut x);
+/// b.encode_with(&options, &mut y);
+/// assert_eq!(x, y);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EncodeOptions {
+    /// Writes map entries in key order, so equal messages encode to the same bytes whatever the
+    /// map backing or the order their maps were filled in. Off by default, since sorting collects
+    /// the entries of each map into a buffer first, while the default writes them straight from the
+    /// map without allocating.
+    pub deterministic: bool,
+}
+
+// The sizes of nested messages in the order the encoder reaches them, like the memoized sizes of
+// protobuf-java but kept beside the message. Measuring records each message before the ones inside
+// it, and encoding reads them back in the same order, so no message is measured twice.
+#[derive(Debug, Default)]
+struct Sizes {
+    lens: Lens,
+    // Measuring and encoding must agree on it, or the recorded sizes would not line up.
+    deterministic: bool,
+}
+
+#[derive(Debug, Default)]
+enum Lens {
+    // Measures every message it is asked about.
+    #[default]
+    Measure,
+    Record(Vec<usize>),
+    Replay {
+        lens: Vec<usize>,
+        next: usize,
+    },
+}
+
+impl Sizes {
+    fn recording(options: &EncodeOptions) -> Self {
+        Sizes {
+            lens: Lens::Record(Vec::new()),
+            deterministic: options.deterministic,
+        }
+    }
+
+    fn replay(&mut self) {
+        if let Lens::Record(lens) = &mut self.lens {
+            self.lens = Lens::Replay {
+                lens: mem::take(lens),
+                next: 0,
+            };
+        }
+    }
+
+    // The size of the next message, which stays next.
+    fn peek(&mut self, m: &Message, d: &MessageDescriptor) -> Result<usize, EncodeError> {
+        match &mut self.lens {
+            Lens::Measure => message_len(m, d, self),
+            Lens::Record(lens) => {
+                let slot = lens.len();
+                lens.push(0);
+                let len = message_len(m, d, self)?;
+                if let Lens::Record(lens) = &mut self.lens {
+                    lens[slot] = len;
+                }
+                Ok(len)
+            }
+            Lens::Replay { lens, next } => Ok(lens[*next]),
+        }
+    }
+
+    // The size of the next message, moving on to the ones inside it.
+    fn take(&mut self, m: &Message, d: &MessageDescriptor) -> Result<usize, EncodeError> {
+        let len = self.peek(m, d)?;
+        if let Lens::Replay { next, .. } = &mut self.lens {
+            *next += 1;
+        }
+        Ok(len)
+    }
+}
+
+fn message_len(
+    m: &Message,
+    d: &MessageDescriptor,
+    sizes: &mut Sizes,
+) -> Result<usize, EncodeError> {
+    if m.fields.len() != d.fields.len() {
+        return Err(EncodeError::invalid_length(d.fields.len(), m.fields.len()));
+    }
+
+    m.fields
+        .iter()
+        .zip(d.fields)
+        .filter_map(|(v, f)| v.as_ref().map(|v| (v, f)))
+        .try_fold(m.unknown_fields.len(), |len, (v, f)| {
+            Ok(len + field_len(v, f, sizes)?)
+        })
+}
+
+fn encode_message(
+    m: &Message,
+    d: &MessageDescriptor,
+    sizes: &mut Sizes,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    if m.fields.len() != d.fields.len() {
+        return Err(EncodeError::invalid_length(d.fields.len(), m.fields.len()));
+    }
+
+    m.fields
+        .iter()
+        .zip(d.fields)
+        .filter_map(|(v, f)| v.as_ref().map(|v| (v, f)))
+        .try_for_each(|(v, f)| encode_value(v, f, sizes, buf))?;
+    buf.extend_from_slice(&m.unknown_fields);
+    Ok(())
+}
+
+// Moves values of closed enums that are not defined by their descriptor into unknown fields.
+pub fn retain_known_enums(m: &mut Message, d: &MessageDescriptor) {
+    let Message {
+        fields,
+        unknown_fields,
+    } = m;
+
+    for (slot, f) in fields.iter_mut().zip(d.fields) {
+        if !matches!(slot, Some(v) if f.matches(v)) {
+            continue;
+        }
+        let rule = match slot {
+            Some(Value::Enum(rule)) => rule,
+            _ => continue,
+        };
+
+        let unknown = match rule {
+            Rule::Singular(v) if !v.is_known(f) => {
+                let unknown = Rule::Singular(*v);
+                *slot = None;
+                unknown
+            }
+            Rule::Singular(_) => continue,
+            Rule::Repeated(v) => {
+                let (known, unknown) = v.iter().partition(|v| v.is_known(f));
+                *v = known;
+                Rule::Repeated(unknown)
+            }
+            Rule::Map(k) => Rule::Map(match k {
+                Key::Bool(v) => Key::Bool(retain_known_entries(v, f)),
+                Key::I32(v) => Key::I32(retain_known_entries(v, f)),
+                Key::I64(v) => Key::I64(retain_known_entries(v, f)),
+                Key::String(v) => Key::String(retain_known_entries(v, f)),
+                Key::U32(v) => Key::U32(retain_known_entries(v, f)),
+                Key::U64(v) => Key::U64(retain_known_entries(v, f)),
+            }),
+        };
+
+        encode_field(&Value::Enum(unknown), f, unknown_fields)
+            .expect("invariant: enum values encode in an enum field");
+    }
+}
+
+fn retain_known_entries<K: KeyVariant>(m: &mut Map<K, Enum>, f: &FieldDescriptor) -> Map<K, Enum> {
+    let (known, unknown) = mem::take(m).into_iter().partition(|(_, v)| v.is_known(f));
+    *m = known;
+    unknown
+}
+
+pub fn decode(buf: &[u8], d: &MessageDescriptor) -> Result<Message, DecodeError> {
+    let mut m = Message::new(d);
+    merge(&mut m, d, buf)?;
+    Ok(m)
+}
+
+pub fn merge(m: &mut Message, d: &MessageDescriptor, buf: &[u8]) -> Result<(), DecodeError> {
+    merge_fields(m, d, &mut Reader::new(buf), None, 0)
+}
+
+fn field_len(v: &Value, f: &FieldDescriptor, sizes: &mut Sizes) -> Result<usize, EncodeError> {
+    if !f.matches(v) {
+        return Err(EncodeError::invalid_type(f, v));
+    }
+
+    match v {
+        Value::Bool(v) => rule_len(v, f, sizes),
+        Value::Bytes(v) => rule_len(v, f, sizes),
+        Value::Enum(v) => rule_len(v, f, sizes),
+        Value::F32(v) => rule_len(v, f, sizes),
+        Value::F64(v) => rule_len(v, f, sizes),
+        Value::I32(v) => rule_len(v, f, sizes),
+        Value::I64(v) => rule_len(v, f, sizes),
+        Value::Message(v) => rule_len(v, f, sizes),
+        Value::String(v) => rule_len(v, f, sizes),
+        Value::U32(v) => rule_len(v, f, sizes),
+        Value::U64(v) => rule_len(v, f, sizes),
+    }
+}
+
+pub(crate) fn encode_field(
+    v: &Value,
+    f: &FieldDescriptor,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    let mut sizes = Sizes::recording(&EncodeOptions::default());
+    field_len(v, f, &mut sizes)?;
+    sizes.replay();
+    encode_value(v, f, &mut sizes, buf)
+}
+
+fn encode_value(
+    v: &Value,
+    f: &FieldDescriptor,
+    sizes: &mut Sizes,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    if !f.matches(v) {
+        return Err(EncodeError::invalid_type(f, v));
+    }
+
+    match v {
+        Value::Bool(v) => encode_rule(v, f, sizes, buf),
+        Value::Bytes(v) => encode_rule(v, f, sizes, buf),
+        Value::Enum(v) => encode_rule(v, f, sizes, buf),
+        Value::F32(v) => encode_rule(v, f, sizes, buf),
+        Value::F64(v) => encode_rule(v, f, sizes, buf),
+        Value::I32(v) => encode_rule(v, f, sizes, buf),
+        Value::I64(v) => encode_rule(v, f, sizes, buf),
+        Value::Message(v) => encode_rule(v, f, sizes, buf),
+        Value::String(v) => encode_rule(v, f, sizes, buf),
+        Value::U32(v) => encode_rule(v, f, sizes, buf),
+        Value::U64(v) => encode_rule(v, f, sizes, buf),
+    }
+}
+
+fn is_packable(wire_type: WireType) -> bool {
+    matches!(
+        wire_type,
+        WireType::Varint | WireType::Fixed32 | WireType::Fixed64
+    )
+}
+
+// Fields without presence are indistinguishable from unset when they hold their default value.
+fn is_implicit_default<T: Element>(v: &T, f: &FieldDescriptor) -> bool {
+    f.presence == Presence::Implicit && v.is_default(f.ty)
+}
+
+fn packed_len<T: Element>(
+    v: &[T],
+    f: &FieldDescriptor,
+    sizes: &mut Sizes,
+) -> Result<usize, EncodeError> {
+    v.iter()
+        .try_fold(0, |len, v| Ok(len + v.len(f.ty, f, sizes)?))
+}
+
+fn rule_len<T: Element>(
+    rule: &Rule<T>,
+    f: &FieldDescriptor,
+    sizes: &mut Sizes,
+) -> Result<usize, EncodeError> {
+    let tag_len = wire::tag_len(f.number);
+    match rule {
+        Rule::Singular(v) if is_implicit_default(v, f) => Ok(0),
+        Rule::Singular(v) => Ok(tag_len + v.len(f.ty, f, sizes).map_err(|e| e.at(f.name))?),
+        Rule::Repeated(v) if v.is_empty() => Ok(0),
+        Rule::Repeated(v) if is_packable(T::wire_type(f.ty)) => {
+            let len = packed_len(v, f, sizes)?;
+            Ok(tag_len + wire::varint_len(len as u64) + len)
+        }
+        Rule::Repeated(v) => v.iter().enumerate().try_fold(0, |len, (i, v)| {
+            Ok(len + tag_len + v.len(f.ty, f, sizes).map_err(|e| e.at_key(f.name, i))?)
+        }),
+        Rule::Map(v) => match v {
+            Key::Bool(v) => map_len(v, f, sizes),
+            Key::I32(v) => map_len(v, f, sizes),
+            Key::I64(v) => map_len(v, f, sizes),
+            Key::String(v) => map_len(v, f, sizes),
+            Key::U32(v) => map_len(v, f, sizes),
+            Key::U64(v) => map_len(v, f, sizes),
+        },
+    }
+}
+
+fn encode_rule<T: Element>(
+    rule: &Rule<T>,
+    f: &FieldDescriptor,
+    sizes: &mut Sizes,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    let wire_type = T::wire_type(f.ty);
+    match rule {
+        Rule::Singular(v) if is_implicit_default(v, f) => Ok(()),
+        Rule::Singular(v) => {
+            wire::encode_tag(f.number, wire_type, buf);
+            v.encode(f.ty, f, sizes, buf).map_err(|e| e.at(f.name))
+        }
+        Rule::Repeated(v) if v.is_empty() => Ok(()),
+        Rule::Repeated(v) if is_packable(wire_type) => {
+            wire::encode_tag(f.number, WireType::Len, buf);
+            wire::encode_varint(packed_len(v, f, sizes)? as u64, buf);
+            v.iter().try_for_each(|v| v.encode(f.ty, f, sizes, buf))
+        }
+        Rule::Repeated(v) => v.iter().enumerate().try_for_each(|(i, v)| {
+            wire::encode_tag(f.number, wire_type, buf);
+            v.encode(f.ty, f, sizes, buf)
+                .map_err(|e| e.at_key(f.name, i))
+        }),
+        Rule::Map(v) => match v {
+            Key::Bool(v) => encode_map(v, f, sizes, buf),
+            Key::I32(v) => encode_map(v, f, sizes, buf),
+            Key::I64(v) => encode_map(v, f, sizes, buf),
+            Key::String(v) => encode_map(v, f, sizes, buf),
+            Key::U32(v) => encode_map(v, f, sizes, buf),
+            Key::U64(v) => encode_map(v, f, sizes, buf),
+        },
+    }
+}
+
+fn entry_len<K: MapKey, T: Element>(
+    k: &K,
+    v: &T,
+    f: &FieldDescriptor,
+    sizes: &mut Sizes,
+) -> Result<usize, EncodeError> {
+    let key = f.key.unwrap();
+    Ok(wire::tag_len(1) + k.len(key, f, sizes)? + wire::tag_len(2) + v.len(f.ty, f, sizes)?)
+}
+
+fn map_len<K: MapKey, T: Element>(
+    m: &Map<K, T>,
+    f: &FieldDescriptor,
+    sizes: &mut Sizes,
+) -> Result<usize, EncodeError> {
+    let mut len = 0;
+    for_each_entry(m, sizes.deterministic, |k, v| {
+        let entry = entry_len(k, v, f, sizes).map_err(|e| e.at_key(f.name, k))?;
+        len += wire::tag_len(f.number) + wire::varint_len(entry as u64) + entry;
+        Ok(())
+    })?;
+    Ok(len)
+}
+
+// Visits the entries in iteration order, or in key order when encoding deterministically, which
+// is the only case that collects them. Measuring and encoding must visit them in the same order
+// for the recorded sizes to line up.
+fn for_each_entry<'a, K: MapKey, T>(
+    m: &'a Map<K, T>,
+    deterministic: bool,
+    mut f: impl FnMut(&'a K, &'a T) -> Result<(), EncodeError>,
+) -> Result<(), EncodeError> {
+    if !deterministic {
+        return m.iter().try_for_each(|(k, v)| f(k, v));
+    }
+    let mut entries: Vec<_> = m.iter().collect();
+    entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    entries.into_iter().try_for_each(|(k, v)| f(k, v))
+}
+
+fn encode_map<K: MapKey, T: Element>(
+    m: &Map<K, T>,
+    f: &FieldDescriptor,
+    sizes: &mut Sizes,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    let key = f.key.unwrap();
+    for_each_entry(m, sizes.deterministic, |k, v| {
+        wire::encode_tag(f.number, WireType::Len, buf);
+        let entry = entry_len(k, v, f, sizes).map_err(|e| e.at_key(f.name, k))?;
+        wire::encode_varint(entry as u64, buf);
+        wire::encode_tag(1, K::wire_type(key), buf);
+        k.encode(key, f, sizes, buf)?;
+        wire::encode_tag(2, T::wire_type(f.ty), buf);
+        v.encode(f.ty, f, sizes, buf)
+            .map_err(|e| e.at_key(f.name, k))
+    })
+}
+
+fn merge_fields(
+    m: &mut Message,
+    d: &MessageDescriptor,
+    r: &mut Reader,
+    group: Option<u32>,
+    depth: usize,
+) -> Result<(), DecodeError> {
+    if depth > RECURSION_LIMIT {
+        return Err(DecodeError::RecursionLimit(RECURSION_LIMIT));
+    }
+
+    if m.fields.len() != d.fields.len() {
+        return Err(AbsorbError::invalid_length(d.fields.len(), m.fields.len()).into());
+    }
+
+    while !r.is_empty() {
+        let start = r.remaining();
+        let (number, wire_type) = r.read_tag()?;
+        if wire_type == WireType::EndGroup {
+            return match group {
+                Some(n) if n == number => Ok(()),
+                _ => Err(DecodeError::UnexpectedEndGroup(number)),
+            };
+        }
+
+        match d.field_by_number(number) {
+            Some((i, f)) => {
+                let unknown = &mut m.unknown_fields;
+                merge_field(&mut m.fields[i], unknown, f, wire_type, r, depth)?
+            }
+            None => {
+                r.skip(number, wire_type, depth)?;
+                let len = start.len() - r.remaining().len();
+                m.unknown_fields.extend_from_slice(&start[..len]);
+            }
+        }
+    }
+
+    match group {
+        Some(_) => Err(DecodeError::Truncated),
+        None => Ok(()),
+    }
+}
+
+fn merge_field(
+    slot: &mut Option<Value>,
+    unknown: &mut Vec<u8>,
+    f: &FieldDescriptor,
+    wire_type: WireType,
+    r: &mut Reader,
+    depth: usize,
+) -> Result<(), DecodeError> {
+    match f.ty {
+        Type::Bool => merge_rule::<bool>(slot, unknown, f, wire_type, r, depth),
+        Type::Bytes => merge_rule::<Vec<u8>>(slot, unknown, f, wire_type, r, depth),
+        Type::Enum => merge_rule::<Enum>(slot, unknown, f, wire_type, r, depth),
+        Type::Float => merge_rule::<f32>(slot, unknown, f, wire_type, r, depth),
+        Type::Double => merge_rule::<f64>(slot, unknown, f, wire_type, r, depth),
+        Type::Int32 | Type::SInt32 | Type::SFixed32 => {
+            merge_rule::<i32>(slot, unknown, f, wire_type, r, depth)
+        }
+        Type::Int64 | Type::SInt64 | Type::SFixed64 => {
+            merge_rule::<i64>(slot, unknown, f, wire_type, r, depth)
+        }
+        Type::Message | Type::Group => merge_rule::<Message>(slot, unknown, f, wire_type, r, depth),
+        Type::String => merge_rule::<String>(slot, unknown, f, wire_type, r, depth),
+        Type::UInt32 | Type::Fixed32 => merge_rule::<u32>(slot, unknown, f, wire_type, r, depth),
+        Type::UInt64 | Type::Fixed64 => merge_rule::<u64>(slot, unknown, f, wire_type, r, depth),
+    }
+}
+
+fn merge_rule<T: Element>(
+    slot: &mut Option<Value>,
+    unknown: &mut Vec<u8>,
+    f: &FieldDescriptor,
+    wire_type: WireType,
+    r: &mut Reader,
+    depth: usize,
+) -> Result<(), DecodeError> {
+    let expected = T::wire_type(f.ty);
+
+    if let Some(key) = f.key {
+        if wire_type != WireType::Len {
+            return Err(DecodeError::wire_type_mismatch(f.name, wire_type));
+        }
+
+        let entry = r.read_bytes()?;
+        return match key {
+            Type::Bool => merge_entry::<bool, T>(slot, unknown, f, entry, depth),
+            Type::Int32 | Type::SInt32 | Type::SFixed32 => {
+                merge_entry::<i32, T>(slot, unknown, f, entry, depth)
+            }
+            Type::Int64 | Type::SInt64 | Type::SFixed64 => {
+                merge_entry::<i64, T>(slot, unknown, f, entry, depth)
+            }
+            Type::String => merge_entry::<String, T>(slot, unknown, f, entry, depth),
+            Type::UInt32 | Type::Fixed32 => merge_entry::<u32, T>(slot, unknown, f, entry, depth),
+            Type::UInt64 | Type::Fixed64 => merge_entry::<u64, T>(slot, unknown, f, entry, depth),
+            Type::Bytes | Type::Double | Type::Enum | Type::Float | Type::Group | Type::Message => {
+                Err(DescriptorError::invalid_key(f.name, key).into())
+            }
+        };
+    }
+
+    if f.label == Label::Repeated {
+        let list = repeated_mut::<T>(slot);
+        let mut push = |v: T| match v.is_known(f) {
+            true => list.push(v),
+            false => push_unknown(unknown, f, &v),
+        };
+        if wire_type == WireType::Len && is_packable(expected) {
+            let mut packed = Reader::new(r.read_bytes()?);
+            while !packed.is_empty() {
+                push(T::decode(&mut packed, f.ty, f, depth)?);
+            }
+        } else if wire_type == expected {
+            push(T::decode(r, f.ty, f, depth)?);
+        } else {
+            return Err(DecodeError::wire_type_mismatch(f.name, wire_type));
+        }
+        return Ok(());
+    }
+
+    if wire_type != expected {
+        return Err(DecodeError::wire_type_mismatch(f.name, wire_type));
+    }
+
+    match slot.as_mut().and_then(T::rule_mut) {
+        Some(Rule::Singular(v)) if T::MERGES => v.merge(r, f.ty, f, depth),
+        _ => {
+            let v = T::decode(r, f.ty, f, depth)?;
+            match v.is_known(f) {
+                true => *slot = Some(T::into_value(Rule::Singular(v))),
+                false => push_unknown(unknown, f, &v),
+            }
+            Ok(())
+        }
+    }
+}
+
+fn push_unknown<T: Element>(unknown: &mut Vec<u8>, f: &FieldDescriptor, v: &T) {
+    wire::encode_tag(f.number, T::wire_type(f.ty), unknown);
+    v.encode(f.ty, f, &mut Sizes::default(), unknown)
+        .expect("invariant: a decoded value encodes in its own field");
+}
+
+fn merge_entry<K: MapKey, T: Element>(
+    slot: &mut Option<Value>,
+    unknown: &mut Vec<u8>,
+    f: &FieldDescriptor,
+    buf: &[u8],
+    depth: usize,
+) -> Result<(), DecodeError> {
+    let key_type = f.key.unwrap();
+    let mut r = Reader::new(buf);
+    let mut key = None;
+    let mut value = None;
+    while !r.is_empty() {
+        match r.read_tag()? {
+            (1, wire_type) if wire_type == K::wire_type(key_type) => {
+                key = Some(K::decode(&mut r, key_type, f, depth)?)
+            }
+            (2, wire_type) if wire_type == T::wire_type(f.ty) => {
+                value = Some(T::decode(&mut r, f.ty, f, depth)?)
+            }
+            (number, wire_type) => r.skip(number, wire_type, depth)?,
+        }
+    }
+
+    let value = match value {
+        Some(v) => v,
+        None => T::default_for(f)?,
+    };
+    if value.is_known(f) {
+        map_mut::<K, T>(slot).insert(key.unwrap_or_default(), value);
+    } else {
+        wire::encode_tag(f.number, WireType::Len, unknown);
+        wire::encode_varint(buf.len() as u64, unknown);
+        unknown.extend_from_slice(buf);
+    }
+    Ok(())
+}
+
+fn repeated_mut<T: Element>(slot: &mut Option<Value>) -> &mut Vec<T> {
+    if !matches!(slot.as_mut().and_then(T::rule_mut), Some(Rule::Repeated(_))) {
+        *slot = Some(T::into_value(Rule::Repeated(Vec::new())));
+    }
+
+    match slot.as_mut().and_then(T::rule_mut) {
+        Some(Rule::Repeated(v)) => v,
+        _ => unreachable!(),
+    }
+}
+
+fn map_mut<K: MapKey, T: Element>(slot: &mut Option<Value>) -> &mut Map<K, T> {
+    let present = match slot.as_mut().and_then(T::rule_mut) {
+        Some(Rule::Map(k)) => K::map_mut(k).is_some(),
+        _ => false,
+    };
+    if !present {
+        *slot = Some(T::into_value(Rule::Map(K::into_key(Map::new()))));
+    }
+
+    match slot.as_mut().and_then(T::rule_mut) {
+        Some(Rule::Map(k)) => K::map_mut(k).unwrap(),
+        _ => unreachable!(),
+    }
+}
+
+// A single element of a field on the wire, excluding its tag.
+trait Element: Variant {
+    // Whether a repeated singular field merges into its previous value instead of replacing it.
+    const MERGES: bool = false;
+
+    fn wire_type(ty: Type) -> WireType;
+    fn len(&self, ty: Type, f: &FieldDescriptor, sizes: &mut Sizes) -> Result<usize, EncodeError>;
+    fn encode(
+        &self,
+        ty: Type,
+        f: &FieldDescriptor,
+        sizes: &mut Sizes,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), EncodeError>;
+    fn decode(
+        r: &mut Reader,
+        ty: Type,
+        f: &FieldDescriptor,
+        depth: usize,
+    ) -> Result<Self, DecodeError>;
+    fn default_for(f: &FieldDescriptor) -> Result<Self, DecodeError>;
+
+    fn is_default(&self, _: Type) -> bool {
+        false
+    }
+
+    // Whether a decoded value is defined by the schema, see retain_known_enums.
+    fn is_known(&self, _: &FieldDescriptor) -> bool {
+        true
+    }
+
+    fn merge(
+        &mut self,
+        r: &mut Reader,
+        ty: Type,
+        f: &FieldDescriptor,
+        depth: usize,
+    ) -> Result<(), DecodeError> {
+        *self = Self::decode(r, ty, f, depth)?;
+        Ok(())
+    }
+}
+
+trait MapKey: Element + KeyVariant + Default + Ord + Display {}
+
+impl<K: Element + KeyVariant + Default + Ord + Display> MapKey for K {}
+
+// A fixed width or varint element, stored on the wire as a raw u64.
+trait Scalar: Variant + Copy + Default {
+    fn raw_wire_type(ty: Type) -> WireType;
+    fn to_raw(self, ty: Type) -> u64;
+    fn from_raw(raw: u64, ty: Type) -> Self;
+
+    fn is_defined(&self, _: &FieldDescriptor) -> bool {
+        true
+    }
+}
+
+impl<T: Scalar> Element for T {
+    fn wire_type(ty: Type) -> WireType {
+        T::raw_wire_type(ty)
+    }
+
+    fn len(&self, ty: Type, _: &FieldDescriptor, _: &mut Sizes) -> Result<usize, EncodeError> {
+        Ok(match T::raw_wire_type(ty) {
+            WireType::Varint => wire::varint_len(self.to_raw(ty)),
+            WireType::Fixed32 => 4,
+            _ => 8,
+        })
+    }
+
+    fn encode(
+        &self,
+        ty: Type,
+        _: &FieldDescriptor,
+        _: &mut Sizes,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), EncodeError> {
+        let raw = self.to_raw(ty);
+        match T::raw_wire_type(ty) {
+            WireType::Varint => wire::encode_varint(raw, buf),
+            WireType::Fixed32 => buf.extend_from_slice(&(raw as u32).to_le_bytes()),
+            _ => buf.extend_from_slice(&raw.to_le_bytes()),
+        }
+        Ok(())
+    }
+
+    fn decode(
+        r: &mut Reader,
+        ty: Type,
+        _: &FieldDescriptor,
+        _: usize,
+    ) -> Result<Self, DecodeError> {
+        let raw = match T::raw_wire_type(ty) {
+            WireType::Varint => r.read_varint()?,
+            WireType::Fixed32 => u64::from(r.read_fixed32()?),
+            _ => r.read_fixed64()?,
+        };
+        Ok(T::from_raw(raw, ty))
+    }
+
+    fn default_for(_: &FieldDescriptor) -> Result<Self, DecodeError> {
+        Ok(T::default())
+    }
+
+    // Compares the raw bits so that -0.0 is still written.
+    fn is_default(&self, ty: Type) -> bool {
+        self.to_raw(ty) == 0
+    }
+
+    fn is_known(&self, f: &FieldDescriptor) -> bool {
+        self.is_defined(f)
+    }
+}
+
+impl Scalar for bool {
+    fn raw_wire_type(_: Type) -> WireType {
+        WireType::Varint
+    }
+
+    fn to_raw(self, _: Type) -> u64 {
+        self as u64
+    }
+
+    fn from_raw(raw: u64, _: Type) -> Self {
+        raw != 0
+    }
+}
+
+impl Scalar for Enum {
+    fn raw_wire_type(_: Type) -> WireType {
+        WireType::Varint
+    }
+
+    fn to_raw(self, _: Type) -> u64 {
+        self.number as i64 as u64
+    }
+
+    fn from_raw(raw: u64, _: Type) -> Self {
+        Enum { number: raw as i32 }
+    }
+
+    fn is_defined(&self, f: &FieldDescriptor) -> bool {
+        match f.enum_descriptor() {
+            Some(d) if d.closed => d.value_by_number(self.number).is_some(),
+            _ => true,
+        }
+    }
+}
+
+impl Scalar for f32 {
+    fn raw_wire_type(_: Type) -> WireType {
+        WireType::Fixed32
+    }
+
+    fn to_raw(self, _: Type) -> u64 {
+        u64::from(self.to_bits())
+    }
+
+    fn from_raw(raw: u64, _: Type) -> Self {
+        f32::from_bits(raw as u32)
+    }
+}
+
+impl Scalar for f64 {
+    fn raw_wire_type(_: Type) -> WireType {
+        WireType::Fixed64
+    }
+
+    fn to_raw(self, _: Type) -> u64 {
+        self.to_bits()
+    }
+
+    fn from_raw(raw: u64, _: Type) -> Self {
+        f64::from_bits(raw)
+    }
+}
+
+impl Scalar for i32 {
+    fn raw_wire_type(ty: Type) -> WireType {
+        match ty {
+            Type::SFixed32 => WireType::Fixed32,
+            _ => WireType::Varint,
+        }
+    }
+
+    fn to_raw(self, ty: Type) -> u64 {
+        match ty {
+            Type::SInt32 => wire::encode_zigzag32(self),
+            Type::SFixed32 => u64::from(self as u32),
+            _ => self as i64 as u64,
+        }
+    }
+
+    fn from_raw(raw: u64, ty: Type) -> Self {
+        match ty {
+            Type::SInt32 => wire::decode_zigzag32(raw),
+            _ => raw as i32,
+        }
+    }
+}
+
+impl Scalar for i64 {
+    fn raw_wire_type(ty: Type) -> WireType {
+        match ty {
+            Type::SFixed64 => WireType::Fixed64,
+            _ => WireType::Varint,
+        }
+    }
+
+    fn to_raw(self, ty: Type) -> u64 {
+        match ty {
+            Type::SInt64 => wire::encode_zigzag64(self),
+            _ => self as u64,
+        }
+    }
+
+    fn from_raw(raw: u64, ty: Type) -> Self {
+        match ty {
+            Type::SInt64 => wire::decode_zigzag64(raw),
+            _ => raw as i64,
+        }
+    }
+}
+
+impl Scalar for u32 {
+    fn raw_wire_type(ty: Type) -> WireType {
+        match ty {
+            Type::Fixed32 => WireType::Fixed32,
+            _ => WireType::Varint,
+        }
+    }
+
+    fn to_raw(self, _: Type) -> u64 {
+        u64::from(self)
+    }
+
+    fn from_raw(raw: u64, _: Type) -> Self {
+        raw as u32
+    }
+}
+
+impl Scalar for u64 {
+    fn raw_wire_type(ty: Type) -> WireType {
+        match ty {
+            Type::Fixed64 => WireType::Fixed64,
+            _ => WireType::Varint,
+        }
+    }
+
+    fn to_raw(self, _: Type) -> u64 {
+        self
+    }
+
+    fn from_raw(raw: u64, _: Type) -> Self {
+        raw
+    }
+}
+
+impl Element for Vec<u8> {
+    fn wire_type(_: Type) -> WireType {
+        WireType::Len
+    }
+
+    fn len(&self, _: Type, _: &FieldDescriptor, _: &mut Sizes) -> Result<usize, EncodeError> {
+        Ok(wire::varint_len(self.len() as u64) + self.len())
+    }
+
+    fn encode(
+        &self,
+        _: Type,
+        _: &FieldDescriptor,
+        _: &mut Sizes,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), EncodeError> {
+        wire::encode_varint(self.len() as u64, buf);
+        buf.extend_from_slice(self);
+        Ok(())
+    }
+
+    fn decode(r: &mut Reader, _: Type, _: &FieldDescriptor, _: usize) -> Result<Self, DecodeError> {
+        Ok(r.read_bytes()?.to_vec())
+    }
+
+    fn default_for(_: &FieldDescriptor) -> Result<Self, DecodeError> {
+        Ok(Vec::new())
+    }
+
+    fn is_default(&self, _: Type) -> bool {
+        self.is_empty()
+    }
+}
+
+impl Element for String {
+    fn wire_type(_: Type) -> WireType {
+        WireType::Len
+    }
+
+    fn len(&self, _: Type, _: &FieldDescriptor, _: &mut Sizes) -> Result<usize, EncodeError> {
+        Ok(wire::varint_len(self.len() as u64) + self.len())
+    }
+
+    fn encode(
+        &self,
+        _: Type,
+        _: &FieldDescriptor,
+        _: &mut Sizes,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), EncodeError> {
+        wire::encode_varint(self.len() as u64, buf);
+        buf.extend_from_slice(self.as_bytes());
+        Ok(())
+    }
+
+    fn decode(r: &mut Reader, _: Type, f: &FieldDescriptor, _: usize) -> Result<Self, DecodeError> {
+        String::from_utf8(r.read_bytes()?.to_vec()).map_err(|_| DecodeError::invalid_utf8(f.name))
+    }
+
+    fn default_for(_: &FieldDescriptor) -> Result<Self, DecodeError> {
+        Ok(String::new())
+    }
+
+    fn is_default(&self, _: Type) -> bool {
+        self.is_empty()
+    }
+}
+
+fn nested_encode(f: &FieldDescriptor) -> Result<&'static MessageDescriptor, EncodeError> {
+    f.message_descriptor()
+        .ok_or_else(|| EncodeError::missing_descriptor(f.name))
+}
+
+fn nested_decode(f: &FieldDescriptor) -> Result<&'static MessageDescriptor, DecodeError> {
+    f.message_descriptor()
+        .ok_or_else(|| DecodeError::missing_descriptor(f.name))
+}
+
+impl Element for Message {
+    const MERGES: bool = true;
+
+    fn wire_type(ty: Type) -> WireType {
+        match ty {
+            Type::Group => WireType::StartGroup,
+            _ => WireType::Len,
+        }
+    }
+
+    fn len(&self, ty: Type, f: &FieldDescriptor, sizes: &mut Sizes) -> Result<usize, EncodeError> {
+        let len = sizes.peek(self, nested_encode(f)?)?;
+        Ok(match ty {
+            Type::Group => len + wire::tag_len(f.number),
+            _ => wire::varint_len(len as u64) + len,
+        })
+    }
+
+    fn encode(
+        &self,
+        ty: Type,
+        f: &FieldDescriptor,
+        sizes: &mut Sizes,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), EncodeError> {
+        let d = nested_encode(f)?;
+        let len = sizes.take(self, d)?;
+        match ty {
+            Type::Group => {
+                encode_message(self, d, sizes, buf)?;
+                wire::encode_tag(f.number, WireType::EndGroup, buf);
+            }
+            _ => {
+                wire::encode_varint(len as u64, buf);
+                encode_message(self, d, sizes, buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode(
+        r: &mut Reader,
+        ty: Type,
+        f: &FieldDescriptor,
+        depth: usize,
+    ) -> Result<Self, DecodeError> {
+        let mut m = Self::default_for(f)?;
+        m.merge(r, ty, f, depth)?;
+        Ok(m)
+    }
+
+    fn default_for(f: &FieldDescriptor) -> Result<Self, DecodeError> {
+        Ok(Message::new(nested_decode(f)?))
+    }
+
+    fn merge(
+        &mut self,
+        r: &mut Reader,
+        ty: Type,
+        f: &FieldDescriptor,
+        depth: usize,
+    ) -> Result<(), DecodeError> {
+        let d = nested_decode(f)?;
+        match ty {
+            Type::Group => merge_fields(self, d, r, Some(f.number), depth + 1),
+            _ => merge_fields(self, d, &mut Reader::new(r.read_bytes()?), None, depth + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        descriptor::{EnumDescriptor, EnumValueDescriptor},
+        enumeration::ProtoEnum,
+        error::ErrorKind,
+        heavy::complex::ComplexEnum,
+    };
+
+    const fn field(
+        name: &'static str,
+        number: u32,
+        ty: Type,
+        label: Label,
+        key: Option<Type>,
+    ) -> FieldDescriptor {
+        FieldDescriptor {
+            name,
+            number,
+            ty,
+            label,
+            presence: match label {
+                Label::Repeated => Presence::Implicit,
+                _ => Presence::Explicit,
+            },
+            key,
+            message: None,
+            enumeration: None,
+            constraints: &[],
+        }
+    }
+
+    static NESTED: MessageDescriptor = MessageDescriptor {
+        name: "Nested",
+        fields: &[field("a", 1, Type::Int32, Label::Optional, None)],
+    };
+
+    static ALL: MessageDescriptor = MessageDescriptor {
+        name: "All",
+        fields: &[
+            field("a", 1, Type::Int32, Label::Optional, None),
+            field("b", 2, Type::String, Label::Optional, None),
+            field("c", 3, Type::SInt32, Label::Optional, None),
+            field("d", 4, Type::Int32, Label::Repeated, None),
+            field("e", 5, Type::String, Label::Repeated, None),
+            field("f", 6, Type::Double, Label::Optional, None),
+            field("g", 7, Type::Fixed32, Label::Optional, None),
+            field("h", 8, Type::String, Label::Repeated, Some(Type::Int32)),
+            field("i", 9, Type::SInt64, Label::Optional, None),
+            field("j", 10, Type::Bytes, Label::Optional, None),
+            FieldDescriptor {
+                message: Some(|| &NESTED),
+                ..field("k", 11, Type::Message, Label::Optional, None)
+            },
+        ],
+    };
+
+    fn message(values: Vec<(usize, Value)>) -> Message {
+        let mut m = Message::new(&ALL);
+        for (i, v) in values {
+            m.fields[i] = Some(v);
+        }
+        m
+    }
+
+    fn nested(a: i32) -> Message {
+        Message {
+            fields: vec![Some(Value::I32(Rule::Singular(a)))],
+            unknown_fields: Vec::new(),
+        }
+    }
+
+    // Deterministic, so that maps with several entries compare equal.
+    fn encoded(m: &Message) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let options = EncodeOptions {
+            deterministic: true,
+        };
+        encode_with(m, &ALL, &options, &mut buf).unwrap();
+        assert_eq!(buf.len(), encoded_len(m, &ALL).unwrap());
+        buf
+    }
+
+    // Each case is what protoc writes for the same field of the same message.
+    #[test]
+    fn encodes_like_protoc() {
+        let cases: Vec<(usize, Value, &[u8])> = vec![
+            (0, Value::I32(Rule::Singular(150)), &[0x08, 0x96, 0x01]),
+            (
+                0,
+                Value::I32(Rule::Singular(-1)),
+                &[
+                    0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+                ],
+            ),
+            (
+                1,
+                Value::String(Rule::Singular("testing".to_owned())),
+                &[0x12, 0x07, 0x74, 0x65, 0x73, 0x74, 0x69, 0x6e, 0x67],
+            ),
+            (2, Value::I32(Rule::Singular(-1)), &[0x18, 0x01]),
+            (
+                3,
+                Value::I32(Rule::Repeated(vec![3, 270, 86942])),
+                &[0x22, 0x06, 0x03, 0x8e, 0x02, 0x9e, 0xa7, 0x05],
+            ),
+            (
+                4,
+                Value::String(Rule::Repeated(vec!["a".to_owned(), "b".to_owned()])),
+                &[0x2a, 0x01, 0x61, 0x2a, 0x01, 0x62],
+            ),
+            (
+                5,
+                Value::F64(Rule::Singular(1.0)),
+                &[0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f],
+            ),
+            (
+                6,
+                Value::U32(Rule::Singular(1)),
+                &[0x3d, 0x01, 0x00, 0x00, 0x00],
+            ),
+            (
+                7,
+                Value::String(Rule::Map(Key::I32(
+                    vec![(1, "a".to_owned())].into_iter().collect(),
+                ))),
+                &[0x42, 0x05, 0x08, 0x01, 0x12, 0x01, 0x61],
+            ),
+            (8, Value::I64(Rule::Singular(-2)), &[0x48, 0x03]),
+            (
+                9,
+                Value::Bytes(Rule::Singular(vec![0xff])),
+                &[0x52, 0x01, 0xff],
+            ),
+            (
+                10,
+                Value::Message(Rule::Singular(nested(1))),
+                &[0x5a, 0x02, 0x08, 0x01],
+            ),
+        ];
+
+        for (i, v, bytes) in cases {
+            let m = message(vec![(i, v)]);
+            assert_eq!(encoded(&m), bytes, "field {}", ALL.fields[i].name);
+            assert_eq!(decode(bytes, &ALL).unwrap(), m);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let m = Message {
+            unknown_fields: vec![0x78, 0x01],
+            ..message(vec![
+                (0, Value::I32(Rule::Singular(i32::MIN))),
+                (1, Value::String(Rule::Singular("é".to_owned()))),
+                (3, Value::I32(Rule::Repeated(vec![0, -1, i32::MAX]))),
+                (5, Value::F64(Rule::Singular(-0.5))),
+                (
+                    7,
+                    Value::String(Rule::Map(Key::I32(
+                        vec![(-1, "x".to_owned()), (2, "".to_owned())]
+                            .into_iter()
+                            .collect(),
+                    ))),
+                ),
+                (8, Value::I64(Rule::Singular(i64::MIN))),
+                (10, Value::Message(Rule::Singular(nested(-3)))),
+            ])
+        };
+        assert_eq!(decode(&encoded(&m), &ALL).unwrap(), m);
+    }
+
+    // The same bytes under every map backing and whatever order the entries went in.
+    #[test]
+    fn deterministic_encoding_sorts_map_entries() {
+        let entries = [(3, "c"), (-1, "n"), (200, "h"), (0, "z"), (7, "s")];
+        let expected = [
+            0x42, 0x0e, 0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x12,
+            0x01, 0x6e, 0x42, 0x05, 0x08, 0x00, 0x12, 0x01, 0x7a, 0x42, 0x05, 0x08, 0x03, 0x12,
+            0x01, 0x63, 0x42, 0x05, 0x08, 0x07, 0x12, 0x01, 0x73, 0x42, 0x06, 0x08, 0xc8, 0x01,
+            0x12, 0x01, 0x68,
+        ];
+        let options = EncodeOptions {
+            deterministic: true,
+        };
+        for rotation in 0..entries.len() {
+            let mut entries = entries;
+            entries.rotate_left(rotation);
+            let map = entries.iter().map(|&(k, v)| (k, v.to_owned())).collect();
+            let m = message(vec![(7, Value::String(Rule::Map(Key::I32(map))))]);
+            for _ in 0..2 {
+                let mut buf = Vec::new();
+                encode_with(&m, &ALL, &options, &mut buf).unwrap();
+                assert_eq!(buf, expected, "rotation {}", rotation);
+            }
+
+            // The default order may differ, but not the entries.
+            let mut buf = Vec::new();
+            encode(&m, &ALL, &mut buf).unwrap();
+            assert_eq!(buf.len(), expected.len());
+            assert_eq!(decode(&buf, &ALL).unwrap(), m);
+        }
+    }
+
+    static TREE: MessageDescriptor = MessageDescriptor {
+        name: "Tree",
+        fields: &[
+            field("leaf", 1, Type::Int32, Label::Optional, None),
+            FieldDescriptor {
+                message: Some(|| &TREE),
+                ..field("children", 2, Type::Message, Label::Repeated, None)
+            },
+            FieldDescriptor {
+                message: Some(|| &TREE),
+                ..field(
+                    "by_key",
+                    3,
+                    Type::Message,
+                    Label::Repeated,
+                    Some(Type::Int32),
+                )
+            },
+            FieldDescriptor {
+                message: Some(|| &TREE),
+                ..field("group", 4, Type::Group, Label::Optional, None)
+            },
+        ],
+    };
+
+    // A tree of the given depth and the number of messages nested inside it. Map entries differ in
+    // size, so sizes replayed in the wrong order would show in the output.
+    fn tree(depth: i32) -> (Message, usize) {
+        let mut m = Message::new(&TREE);
+        m.fields[0] = Some(Value::I32(Rule::Singular(depth)));
+        if depth == 0 {
+            return (m, 0);
+        }
+        let (child, n) = tree(depth - 1);
+        m.fields[1] = Some(Value::Message(Rule::Repeated(vec![
+            child.clone(),
+            child.clone(),
+        ])));
+        m.fields[2] = Some(Value::Message(Rule::Map(Key::I32(
+            vec![
+                (1, child.clone()),
+                (-2, Message::new(&TREE)),
+                (3, Message::new(&TREE)),
+                (300, Message::new(&TREE)),
+            ]
+            .into_iter()
+            .collect(),
+        ))));
+        m.fields[3] = Some(Value::Message(Rule::Singular(child)));
+        (m, 4 * (n + 1) + 3)
+    }
+
+    #[test]
+    fn measures_each_nested_message_once() {
+        let (m, nested) = tree(3);
+        let mut sizes = Sizes::recording(&EncodeOptions::default());
+        let len = message_len(&m, &TREE, &mut sizes).unwrap();
+        match &sizes.lens {
+            Lens::Record(lens) => assert_eq!(lens.len(), nested),
+            s => panic!("{:?}", s),
+        }
+
+        sizes.replay();
+        let mut buf = Vec::new();
+        encode_message(&m, &TREE, &mut sizes, &mut buf).unwrap();
+        match sizes.lens {
+            Lens::Replay { lens, next } => assert_eq!(next, lens.len()),
+            s => panic!("{:?}", s),
+        }
+        assert_eq!(buf.len(), len);
+    }
+
+    #[test]
+    fn recorded_sizes_encode_like_measuring_each_time() {
+        for (depth, deterministic) in (0..4).flat_map(|d| [(d, false), (d, true)]) {
+            let (m, _) = tree(depth);
+            let mut uncached = Vec::new();
+            let mut sizes = Sizes {
+                deterministic,
+                ..Sizes::default()
+            };
+            encode_message(&m, &TREE, &mut sizes, &mut uncached).unwrap();
+
+            let mut buf = Vec::new();
+            encode_with(&m, &TREE, &EncodeOptions { deterministic }, &mut buf).unwrap();
+            assert_eq!(buf, uncached, "depth {}", depth);
+            assert_eq!(buf.len(), encoded_len(&m, &TREE).unwrap());
+            assert_eq!(decode(&buf, &TREE).unwrap(), m);
+        }
+    }
+
+    #[test]
+    fn decodes_unpacked_and_packed_repeated() {
+        let unpacked = [0x20, 0x03, 0x20, 0x8e, 0x02];
+        let packed = [0x22, 0x03, 0x03, 0x8e, 0x02];
+        let both = [&unpacked[..], &packed[..]].concat();
+
+        let m = decode(&both, &ALL).unwrap();
+        assert_eq!(
+            m.fields[3],
+            Some(Value::I32(Rule::Repeated(vec![3, 270, 3, 270])))
+        );
+    }
+
+    #[test]
+    fn merges_repeated_occurrences() {
+        // The last scalar wins, while messages merge field by field.
+        let bytes = [0x08, 0x01, 0x08, 0x02, 0x5a, 0x02, 0x08, 0x05, 0x5a, 0x00];
+        let m = decode(&bytes, &ALL).unwrap();
+        assert_eq!(m.fields[0], Some(Value::I32(Rule::Singular(2))));
+        assert_eq!(
+            m.fields[10],
+            Some(Value::Message(Rule::Singular(nested(5))))
+        );
+    }
+
+    #[test]
+    fn fills_in_missing_map_key_and_value() {
+        let m = decode(&[0x42, 0x00], &ALL).unwrap();
+        assert_eq!(
+            m.fields[7],
+            Some(Value::String(Rule::Map(Key::I32(
+                vec![(0, String::new())].into_iter().collect()
+            ))))
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_fields() {
+        // Field 15 as a varint, field 16 as bytes and field 17 as a group, around a known field.
+        let bytes = [
+            0x78, 0x01, 0x08, 0x01, 0x82, 0x01, 0x01, 0x61, 0x8b, 0x01, 0x08, 0x01, 0x8c, 0x01,
+        ];
+        let m = decode(&bytes, &ALL).unwrap();
+        assert_eq!(m.fields[0], Some(Value::I32(Rule::Singular(1))));
+        assert_eq!(
+            m.unknown_fields,
+            [0x78, 0x01, 0x82, 0x01, 0x01, 0x61, 0x8b, 0x01, 0x08, 0x01, 0x8c, 0x01]
+        );
+
+        // Known fields are written first, then the unknown ones as they were read.
+        assert_eq!(encoded(&m)[..2], [0x08, 0x01]);
+        assert_eq!(encoded(&m)[2..], m.unknown_fields[..]);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let cases: &[&[u8]] = &[
+            &[0x08],
+            &[0x08, 0x96],
+            &[0x12, 0x07, 0x74],
+            &[0x31, 0x00, 0x00],
+            &[0x3d, 0x01],
+            &[0x5a, 0x02, 0x08],
+            &[0x8b, 0x01, 0x08, 0x01],
+        ];
+        for bytes in cases {
+            assert!(
+                matches!(decode(bytes, &ALL), Err(DecodeError::Truncated)),
+                "{:x?}",
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_wire_types() {
+        assert!(matches!(
+            decode(&[0x0f], &ALL),
+            Err(DecodeError::InvalidWireType(7))
+        ));
+        // Field a is an int32, so it cannot be length delimited.
+        assert!(matches!(
+            decode(&[0x0a, 0x00], &ALL),
+            Err(DecodeError::WireTypeMismatch { .. })
+        ));
+        assert!(matches!(
+            decode(&[0x45, 0, 0, 0, 0], &ALL),
+            Err(DecodeError::WireTypeMismatch { .. })
+        ));
+        assert!(matches!(
+            decode(&[0x0c], &ALL),
+            Err(DecodeError::UnexpectedEndGroup(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_varints() {
+        let mut bytes = vec![0x08];
+        bytes.extend_from_slice(&[0xff; 10]);
+        assert!(matches!(
+            decode(&bytes, &ALL),
+            Err(DecodeError::InvalidVarint)
+        ));
+        assert!(matches!(
+            decode(&[0x00], &ALL),
+            Err(DecodeError::InvalidFieldNumber(0))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        assert!(matches!(
+            decode(&[0x12, 0x01, 0xff], &ALL),
+            Err(DecodeError::InvalidUtf8 { .. })
+        ));
+    }
+
+    #[test]
+    fn limits_recursion() {
+        static DEEP: MessageDescriptor = MessageDescriptor {
+            name: "Deep",
+            fields: &[FieldDescriptor {
+                message: Some(|| &DEEP),
+                ..field("deep", 1, Type::Message, Label::Optional, None)
+            }],
+        };
+
+        let mut bytes = Vec::new();
+        for _ in 0..=RECURSION_LIMIT + 1 {
+            let mut outer = vec![0x0a];
+            wire::encode_varint(bytes.len() as u64, &mut outer);
+            outer.extend_from_slice(&bytes);
+            bytes = outer;
+        }
+        assert!(matches!(
+            decode(&bytes, &DEEP),
+            Err(DecodeError::RecursionLimit(_))
+        ));
+
+        // Unknown groups nest as deep as the input goes unless skipping them is limited too.
+        let bytes = [0xa3, 0x06].repeat(1_000_000);
+        assert!(matches!(
+            decode(&bytes, &DEEP),
+            Err(DecodeError::RecursionLimit(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_map_keys_that_cannot_key_a_map() {
+        static FLOAT_KEY: MessageDescriptor = MessageDescriptor {
+            name: "FloatKey",
+            fields: &[field(
+                "m",
+                1,
+                Type::String,
+                Label::Repeated,
+                Some(Type::Double),
+            )],
+        };
+
+        // Such a field has no empty value, so a new message leaves it unset.
+        assert!(Value::empty(&FLOAT_KEY.fields[0]).is_none());
+        assert_eq!(Message::new(&FLOAT_KEY).fields, [None]);
+
+        let entry = [0x0a, 0x02, 0x12, 0x00];
+        let e = decode(&entry, &FLOAT_KEY).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidKey);
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        let m = message(vec![(0, Value::String(Rule::Singular("1".to_owned())))]);
+        match encode(&m, &ALL, &mut Vec::new()) {
+            Err(EncodeError::InvalidType {
+                path,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(path, "a");
+                assert_eq!(expected, "Value::I32(Rule::Singular())");
+                assert_eq!(actual, "Value::String(Rule::Singular())");
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+
+        // Errors in nested messages carry the path to them.
+        let mut inner = nested(1);
+        inner.fields[0] = Some(Value::Bool(Rule::Singular(true)));
+        let m = message(vec![(10, Value::Message(Rule::Singular(inner)))]);
+        match encoded_len(&m, &ALL) {
+            Err(EncodeError::InvalidType { path, .. }) => assert_eq!(path, "k.a"),
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let m = Message {
+            fields: Vec::new(),
+            unknown_fields: Vec::new(),
+        };
+        assert!(matches!(
+            encoded_len(&m, &ALL),
+            Err(EncodeError::InvalidLength { .. })
+        ));
+    }
+
+    static OPEN: EnumDescriptor = EnumDescriptor {
+        name: "Open",
+        closed: false,
+        values: &[EnumValueDescriptor {
+            name: "ZERO",
+            number: 0,
+        }],
+    };
+
+    static ENUMS: MessageDescriptor = MessageDescriptor {
+        name: "Enums",
+        fields: &[
+            FieldDescriptor {
+                enumeration: Some(ComplexEnum::descriptor),
+                ..field("closed", 1, Type::Enum, Label::Optional, None)
+            },
+            FieldDescriptor {
+                enumeration: Some(ComplexEnum::descriptor),
+                ..field("closed_list", 2, Type::Enum, Label::Repeated, None)
+            },
+            FieldDescriptor {
+                enumeration: Some(ComplexEnum::descriptor),
+                ..field(
+                    "closed_map",
+                    3,
+                    Type::Enum,
+                    Label::Repeated,
+                    Some(Type::Int32),
+                )
+            },
+            FieldDescriptor {
+                enumeration: Some(|| &OPEN),
+                ..field("open", 4, Type::Enum, Label::Optional, None)
+            },
+        ],
+    };
+
+    fn enums(values: Vec<(usize, Value)>) -> Message {
+        let mut m = Message::new(&ENUMS);
+        for (i, v) in values {
+            m.fields[i] = Some(v);
+        }
+        m
+    }
+
+    #[test]
+    fn keeps_unknown_open_enum_values() {
+        let bytes = [0x20, 0x07];
+        let m = decode(&bytes, &ENUMS).unwrap();
+        assert_eq!(
+            m.fields[3],
+            Some(Value::Enum(Rule::Singular(Enum { number: 7 })))
+        );
+        assert!(m.unknown_fields.is_empty());
+
+        let mut buf = Vec::new();
+        encode(&m, &ENUMS, &mut buf).unwrap();
+        assert_eq!(buf, bytes);
+    }
+
+    #[test]
+    fn moves_unknown_closed_enum_values_to_unknown_fields_on_decode() {
+        // closed = 7, closed_list = [1, 7] unpacked, closed_map = {1: 7}
+        let bytes = [
+            0x08, 0x07, 0x10, 0x01, 0x10, 0x07, 0x1a, 0x04, 0x08, 0x01, 0x10, 0x07,
+        ];
+        let m = decode(&bytes, &ENUMS).unwrap();
+        assert_eq!(m.fields[0], None);
+        assert_eq!(
+            m.fields[1],
+            Some(Value::Enum(Rule::Repeated(vec![Enum { number: 1 }])))
+        );
+        assert_eq!(
+            m.fields[2],
+            Some(Value::Enum(Rule::Map(Key::I32(Map::new()))))
+        );
+        assert_eq!(
+            m.unknown_fields,
+            [0x08, 0x07, 0x10, 0x07, 0x1a, 0x04, 0x08, 0x01, 0x10, 0x07]
+        );
+    }
+
+    #[test]
+    fn retain_known_enums() {
+        let e = |number| Enum { number };
+        let mut m = enums(vec![
+            (0, Value::Enum(Rule::Singular(e(7)))),
+            (1, Value::Enum(Rule::Repeated(vec![e(1), e(7), e(10)]))),
+            (
+                2,
+                Value::Enum(Rule::Map(Key::I32(
+                    vec![(1, e(2)), (2, e(8))].into_iter().collect(),
+                ))),
+            ),
+            (3, Value::Enum(Rule::Singular(e(9)))),
+        ]);
+        super::retain_known_enums(&mut m, &ENUMS);
+
+        assert_eq!(m.fields[0], None);
+        assert_eq!(
+            m.fields[1],
+            Some(Value::Enum(Rule::Repeated(vec![e(1), e(10)])))
+        );
+        assert_eq!(
+            m.fields[2],
+            Some(Value::Enum(Rule::Map(Key::I32(
+                vec![(1, e(2))].into_iter().collect()
+            ))))
+        );
+        // Open enums keep any number.
+        assert_eq!(m.fields[3], Some(Value::Enum(Rule::Singular(e(9)))));
+        // The moved values, as they would have been encoded.
+        assert_eq!(
+            m.unknown_fields,
+            [0x08, 0x07, 0x12, 0x01, 0x07, 0x1a, 0x04, 0x08, 0x02, 0x10, 0x08]
+        );
+    }
+}
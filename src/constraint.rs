@@ -0,0 +1,404 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    sync::{OnceLock, RwLock},
+};
+
+use regex::Regex;
+
+use crate::{
+    descriptor::{Constraint, FieldDescriptor, MessageDescriptor, Number},
+    eq::{self, EqOptions},
+    error::{ConstraintError, DescriptorError, Violation},
+    validate,
+    value::{Message, Value, ValueRef},
+};
+
+/// Checks the constraints of every field in a message, including nested messages, and reports
+/// every violation with the path of the field that broke it. The message is expected to be valid
+/// for the descriptor. A pattern that is not a valid regular expression fails the check with a
+/// DescriptorError; the derive rejects one at compile time.
+///
+/// Derived types set constraints with field attributes: `min_len`, `max_len` and `pattern` for
+/// strings and bytes, `min` and `max` for numbers, `min_items`, `max_items` and `unique` for
+/// repeated fields, `required_key` for maps and `defined_only` for enums. A constraint on a
+/// repeated or map field applies to each element, except for the ones about the collection.
+///
+/// ```
+/// use tobu_conversion::{message::ProtoMessage, Reflect};
+///
+/// #[derive(Debug, Clone, Default, Reflect)]
+/// struct User {
+///     #[proto(number = 1, ty = "string", min_len = 1, max_len = 32, pattern = "^[a-z_]+$")]
+///     name: String,
+///     #[proto(number = 2, ty = "int32", min = 0, max = 150)]
+///     age: i32,
+/// }
+///
+/// let user = User {
+///     name: "Ada".to_owned(),
+///     age: 200,
+/// };
+/// assert_eq!(user.check_constraints().unwrap_err().violations().len(), 2);
+/// ```
+pub fn check(m: &Message, d: &MessageDescriptor) -> Result<(), ConstraintError> {
+    let mut violations = Vec::new();
+    visit_message(m, d, "", &mut violations)?;
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err(ConstraintError::Violations { violations }),
+    }
+}
+
+fn visit_message(
+    m: &Message,
+    d: &MessageDescriptor,
+    prefix: &str,
+    out: &mut Vec<Violation>,
+) -> Result<(), DescriptorError> {
+    for (v, f) in m.fields.iter().zip(d.fields) {
+        // An unset field without presence reads as its default, so the constraints still apply.
+        if let Some(v) = eq::effective(v, f) {
+            visit_value(&v, f, &format!("{}{}", prefix, f.name), out)?;
+        }
+    }
+    Ok(())
+}
+
+fn visit_value(
+    v: &Value,
+    f: &FieldDescriptor,
+    path: &str,
+    out: &mut Vec<Violation>,
+) -> Result<(), DescriptorError> {
+    let elements = validate::element_refs(v);
+    if !v.is_singular() {
+        for c in f.constraints {
+            if !check_collection(c, &elements, f) {
+                out.push(violation(path, c));
+            }
+        }
+    }
+
+    for (key, e) in &elements {
+        let path = match key {
+            Some(key) => format!("{}[{}]", path, key),
+            None => path.to_owned(),
+        };
+        for c in f.constraints {
+            if !check_element(c, *e, f)? {
+                out.push(violation(&path, c));
+            }
+        }
+        if let (ValueRef::Message(m), Some(d)) = (e, f.message_descriptor()) {
+            visit_message(m, d, &format!("{}.", path), out)?;
+        }
+    }
+    Ok(())
+}
+
+fn violation(path: &str, c: &Constraint) -> Violation {
+    Violation {
+        path: path.to_owned(),
+        constraint: *c,
+    }
+}
+
+fn check_collection(
+    c: &Constraint,
+    elements: &[(Option<String>, ValueRef)],
+    f: &FieldDescriptor,
+) -> bool {
+    match c {
+        Constraint::MinItems(n) => elements.len() >= *n,
+        Constraint::MaxItems(n) => elements.len() <= *n,
+        Constraint::Unique if f.is_repeated() => elements.iter().enumerate().all(|(i, (_, a))| {
+            !elements[..i]
+                .iter()
+                .any(|(_, b)| eq::equal_elements(*a, *b, f, &EqOptions::default()))
+        }),
+        Constraint::RequiredKey(key) if f.is_map() => {
+            elements.iter().any(|(k, _)| k.as_deref() == Some(*key))
+        }
+        _ => true,
+    }
+}
+
+fn check_element(
+    c: &Constraint,
+    e: ValueRef,
+    f: &FieldDescriptor,
+) -> Result<bool, DescriptorError> {
+    Ok(match (c, e) {
+        (Constraint::MinLen(n), ValueRef::String(s)) => s.chars().count() >= *n,
+        (Constraint::MinLen(n), ValueRef::Bytes(b)) => b.len() >= *n,
+        (Constraint::MaxLen(n), ValueRef::String(s)) => s.chars().count() <= *n,
+        (Constraint::MaxLen(n), ValueRef::Bytes(b)) => b.len() <= *n,
+        (Constraint::Pattern(p), ValueRef::String(s)) => compile(p, f)?.is_match(s),
+        (Constraint::Min(n), e) => number(e)
+            .is_none_or(|v| matches!(compare(v, *n), Some(Ordering::Greater | Ordering::Equal))),
+        (Constraint::Max(n), e) => number(e)
+            .is_none_or(|v| matches!(compare(v, *n), Some(Ordering::Less | Ordering::Equal))),
+        (Constraint::DefinedOnly, ValueRef::Enum(e)) => f
+            .enum_descriptor()
+            .is_none_or(|d| d.value_by_number(e.number).is_some()),
+        _ => true,
+    })
+}
+
+fn number(e: ValueRef) -> Option<Number> {
+    match e {
+        ValueRef::I32(v) => Some(Number::Int(v.into())),
+        ValueRef::I64(v) => Some(Number::Int(v.into())),
+        ValueRef::U32(v) => Some(Number::Int(v.into())),
+        ValueRef::U64(v) => Some(Number::Int(v.into())),
+        ValueRef::F32(v) => Some(Number::Float(v.into())),
+        ValueRef::F64(v) => Some(Number::Float(v)),
+        _ => None,
+    }
+}
+
+// None if either side is NaN, which fails every bound.
+fn compare(a: Number, b: Number) -> Option<Ordering> {
+    match (a, b) {
+        (Number::Int(a), Number::Int(b)) => Some(a.cmp(&b)),
+        (Number::Int(a), Number::Float(b)) => (a as f64).partial_cmp(&b),
+        (Number::Float(a), Number::Int(b)) => a.partial_cmp(&(b as f64)),
+        (Number::Float(a), Number::Float(b)) => a.partial_cmp(&b),
+    }
+}
+
+// Patterns are compiled once and shared between threads, including the ones that fail to
+// compile, so they are only reported and not compiled again. Checks only take the read lock once
+// their pattern is in.
+static PATTERNS: OnceLock<RwLock<HashMap<&'static str, Result<Regex, regex::Error>>>> =
+    OnceLock::new();
+
+fn compile(pattern: &'static str, f: &FieldDescriptor) -> Result<Regex, DescriptorError> {
+    let patterns = PATTERNS.get_or_init(Default::default);
+    // A panic while holding the lock cannot leave the map half updated.
+    let cached = patterns
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(pattern)
+        .cloned();
+    let compiled = match cached {
+        Some(compiled) => compiled,
+        None => {
+            // Compiled outside the lock, so checks against other patterns are not held up. A
+            // thread that compiled it meanwhile has its result kept.
+            let compiled = Regex::new(pattern);
+            patterns
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(pattern)
+                .or_insert(compiled)
+                .clone()
+        }
+    };
+    compiled.map_err(|e| DescriptorError::invalid_pattern(f.name, pattern, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        descriptor::{EnumDescriptor, EnumValueDescriptor, Label, Presence, Type},
+        error::ErrorKind,
+        value::{Enum, Key, Map, Rule},
+    };
+
+    const fn field(
+        name: &'static str,
+        number: u32,
+        ty: Type,
+        label: Label,
+        constraints: &'static [Constraint],
+    ) -> FieldDescriptor {
+        FieldDescriptor {
+            name,
+            number,
+            ty,
+            label,
+            presence: Presence::Implicit,
+            key: None,
+            message: None,
+            enumeration: None,
+            constraints,
+        }
+    }
+
+    static OPEN: EnumDescriptor = EnumDescriptor {
+        name: "Open",
+        closed: false,
+        values: &[EnumValueDescriptor {
+            name: "ZERO",
+            number: 0,
+        }],
+    };
+
+    static CHECKED: MessageDescriptor = MessageDescriptor {
+        name: "Checked",
+        fields: &[
+            field(
+                "name",
+                1,
+                Type::String,
+                Label::Optional,
+                &[
+                    Constraint::MinLen(2),
+                    Constraint::MaxLen(4),
+                    Constraint::Pattern("^[a-z]+$"),
+                ],
+            ),
+            field(
+                "blob",
+                2,
+                Type::Bytes,
+                Label::Optional,
+                &[Constraint::MaxLen(1)],
+            ),
+            field(
+                "count",
+                3,
+                Type::Int64,
+                Label::Optional,
+                &[
+                    Constraint::Min(Number::Int(0)),
+                    Constraint::Max(Number::Int(10)),
+                ],
+            ),
+            field(
+                "ratio",
+                4,
+                Type::Double,
+                Label::Optional,
+                &[Constraint::Min(Number::Float(0.0))],
+            ),
+            field(
+                "ids",
+                5,
+                Type::Int32,
+                Label::Repeated,
+                &[
+                    Constraint::MinItems(1),
+                    Constraint::MaxItems(2),
+                    Constraint::Unique,
+                    Constraint::Max(Number::Int(100)),
+                ],
+            ),
+            FieldDescriptor {
+                key: Some(Type::String),
+                ..field(
+                    "tags",
+                    6,
+                    Type::Int32,
+                    Label::Repeated,
+                    &[Constraint::RequiredKey("id")],
+                )
+            },
+            FieldDescriptor {
+                enumeration: Some(|| &OPEN),
+                ..field(
+                    "kind",
+                    7,
+                    Type::Enum,
+                    Label::Optional,
+                    &[Constraint::DefinedOnly],
+                )
+            },
+        ],
+    };
+
+    fn message(
+        name: &str,
+        blob: &[u8],
+        count: i64,
+        ratio: f64,
+        ids: &[i32],
+        tags: &[&str],
+        kind: i32,
+    ) -> Message {
+        Message {
+            fields: vec![
+                Some(Value::String(Rule::Singular(name.to_owned()))),
+                Some(Value::Bytes(Rule::Singular(blob.to_vec()))),
+                Some(Value::I64(Rule::Singular(count))),
+                Some(Value::F64(Rule::Singular(ratio))),
+                Some(Value::I32(Rule::Repeated(ids.to_vec()))),
+                Some(Value::I32(Rule::Map(Key::String(
+                    tags.iter()
+                        .map(|t| (t.to_string(), 0))
+                        .collect::<Map<_, _>>(),
+                )))),
+                Some(Value::Enum(Rule::Singular(Enum { number: kind }))),
+            ],
+            unknown_fields: Vec::new(),
+        }
+    }
+
+    fn violations(m: &Message) -> Vec<String> {
+        match check(m, &CHECKED) {
+            Ok(()) => Vec::new(),
+            Err(e) => e.violations().iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn passes() {
+        let m = message("abc", b"x", 10, 0.0, &[1, 2], &["id"], 0);
+        assert!(check(&m, &CHECKED).is_ok());
+    }
+
+    #[test]
+    fn reports_every_violation() {
+        let m = message("A", b"xy", 11, f64::NAN, &[7, 7, 101], &["x"], 3);
+        assert_eq!(
+            violations(&m),
+            [
+                "Field name violates min_len = 2",
+                "Field name violates pattern = \"^[a-z]+$\"",
+                "Field blob violates max_len = 1",
+                "Field count violates max = 10",
+                "Field ratio violates min = 0",
+                "Field ids violates max_items = 2",
+                "Field ids violates unique",
+                "Field ids[2] violates max = 100",
+                "Field tags violates required_key = \"id\"",
+                "Field kind violates defined_only",
+            ]
+        );
+
+        let m = message("abcde", b"", -1, 1.0, &[], &["id"], 0);
+        assert_eq!(
+            violations(&m),
+            [
+                "Field name violates max_len = 4",
+                "Field count violates min = 0",
+                "Field ids violates min_items = 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_patterns() {
+        static BAD: MessageDescriptor = MessageDescriptor {
+            name: "Bad",
+            fields: &[field(
+                "name",
+                1,
+                Type::String,
+                Label::Optional,
+                &[Constraint::Pattern("(")],
+            )],
+        };
+        let m = Message {
+            fields: vec![Some(Value::String(Rule::Singular("x".to_owned())))],
+            unknown_fields: Vec::new(),
+        };
+        for _ in 0..2 {
+            let e = check(&m, &BAD).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidPattern);
+        }
+    }
+}
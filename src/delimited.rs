@@ -0,0 +1,279 @@
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+};
+
+use crate::{
+    codec::{self, EncodeOptions},
+    descriptor::MessageDescriptor,
+    error::{DecodeError, StreamError},
+    message::{self, ProtoMessage},
+    reflect::Reflection,
+    value::Message,
+};
+
+pub const DEFAULT_SIZE_LIMIT: usize = 64 << 20;
+
+/// Writes the message prefixed with its length as a varint, like Java's writeDelimitedTo, so many
+/// messages can be stored or piped back to back.
+pub fn write_delimited<W: Write>(
+    m: &Message,
+    d: &MessageDescriptor,
+    w: &mut W,
+) -> Result<(), StreamError> {
+    let mut buf = Vec::new();
+    codec::encode_length_delimited(m, d, &EncodeOptions::default(), &mut buf)?;
+    w.write_all(&buf)?;
+    Ok(())
+}
+
+/// Reads a message written by write_delimited. Returns None at the end of the stream, and an
+/// error if it ends inside a message or the message is longer than limit. The limit is checked
+/// before anything is allocated.
+pub fn read_delimited<R: Read>(
+    r: &mut R,
+    d: &MessageDescriptor,
+    limit: usize,
+) -> Result<Option<Message>, StreamError> {
+    let buf = match read_frame(r, limit)? {
+        Some(buf) => buf,
+        None => return Ok(None),
+    };
+    let m = codec::decode(&buf, d)?;
+    message::check_initialized(&m, d).map_err(DecodeError::from)?;
+    Ok(Some(m))
+}
+
+fn read_frame<R: Read>(r: &mut R, limit: usize) -> Result<Option<Vec<u8>>, StreamError> {
+    let mut len = 0u64;
+    for i in 0..10 {
+        let b = match read_byte(r)? {
+            Some(b) => b,
+            None if i == 0 => return Ok(None),
+            None => return Err(DecodeError::Truncated.into()),
+        };
+        if i == 9 && b > 1 {
+            return Err(DecodeError::InvalidVarint.into());
+        }
+        len |= u64::from(b & 0x7f) << (7 * i);
+        if b < 0x80 {
+            break;
+        }
+    }
+    // Checked before allocating, so a corrupt prefix cannot claim a huge buffer.
+    if len > limit as u64 {
+        return Err(StreamError::too_large(len, limit));
+    }
+
+    let mut buf = vec![0; len as usize];
+    r.read_exact(&mut buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => StreamError::from(DecodeError::Truncated),
+        _ => StreamError::from(e),
+    })?;
+    Ok(Some(buf))
+}
+
+fn read_byte<R: Read>(r: &mut R) -> io::Result<Option<u8>> {
+    let mut b = [0];
+    loop {
+        match r.read(&mut b) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(b[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads length-delimited messages of type T until the end of the stream. Iteration stops after
+/// the first error, since the position in the stream is lost.
+///
+/// ```
+/// use tobu_conversion::{
+///     delimited::{DelimitedReader, DelimitedWriter},
+///     heavy::simple::Simple,
+/// };
+///
+/// let mut writer = DelimitedWriter::new(Vec::new());
+/// writer.write(&Simple::default()).unwrap();
+/// writer.write(&Simple { simple_bool: true, ..Simple::default() }).unwrap();
+/// let buf = writer.into_inner();
+///
+/// let reader = DelimitedReader::<_, Simple>::new(&buf[..]).with_limit(1 << 20);
+/// let read: Vec<_> = reader.map(|m| m.unwrap().absorb().unwrap()).collect();
+/// assert!(read[1].simple_bool);
+/// ```
+pub struct DelimitedReader<R, T> {
+    reader: R,
+    limit: usize,
+    done: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<R: Read, T: ProtoMessage> DelimitedReader<R, T> {
+    pub fn new(reader: R) -> Self {
+        DelimitedReader {
+            reader,
+            limit: DEFAULT_SIZE_LIMIT,
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The largest message to accept, in bytes. DEFAULT_SIZE_LIMIT unless set.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read, T: ProtoMessage> Iterator for DelimitedReader<R, T> {
+    type Item = Result<Reflection<T>, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = match read_delimited(&mut self.reader, T::descriptor(), self.limit) {
+            Ok(Some(m)) => T::try_from(m)
+                .map(Reflection::new)
+                .map_err(|e| DecodeError::from(e).into()),
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => Err(e),
+        };
+        self.done = next.is_err();
+        Some(next)
+    }
+}
+
+/// Writes messages of type T to a stream, each prefixed with its length, for DelimitedReader or
+/// Java's parseDelimitedFrom to read back.
+pub struct DelimitedWriter<W, T> {
+    writer: W,
+    _marker: PhantomData<T>,
+}
+
+impl<W: Write, T: ProtoMessage> DelimitedWriter<W, T> {
+    pub fn new(writer: W) -> Self {
+        DelimitedWriter {
+            writer,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn write(&mut self, m: &T) -> Result<(), StreamError> {
+        write_delimited(&m.as_message(), T::descriptor(), &mut self.writer)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::ErrorKind,
+        heavy::complex::{Complex, ComplexEnum},
+    };
+
+    fn read(bytes: &[u8]) -> Result<Option<Message>, StreamError> {
+        read_delimited(&mut &bytes[..], Complex::descriptor(), DEFAULT_SIZE_LIMIT)
+    }
+
+    // Both readers fail the same way on the same input.
+    fn read_err(bytes: &[u8]) -> ErrorKind {
+        let e = read(bytes).unwrap_err();
+        let mut reader = DelimitedReader::<_, Complex>::new(bytes);
+        assert_eq!(reader.next().unwrap().unwrap_err().kind(), e.kind());
+        assert!(reader.next().is_none());
+        e.kind()
+    }
+
+    #[test]
+    fn ends_cleanly_between_messages() {
+        assert!(read(&[]).unwrap().is_none());
+        assert!(DelimitedReader::<_, Complex>::new(&[][..]).next().is_none());
+        // An empty message is a zero length prefix, not the end of the stream.
+        assert_eq!(read(&[0x00]).unwrap(), Some(Complex::default().into()));
+    }
+
+    #[test]
+    fn rejects_streams_cut_inside_a_message() {
+        // Inside the length prefix.
+        assert_eq!(read_err(&[0x80]), ErrorKind::Truncated);
+        // Inside the body.
+        assert_eq!(read_err(&[0x05, 0x08, 0x01]), ErrorKind::Truncated);
+    }
+
+    #[test]
+    fn rejects_lengths_over_the_limit_before_allocating() {
+        // A prefix claiming u64::MAX bytes, which could never be allocated.
+        let prefix = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(read_err(&prefix), ErrorKind::TooLarge);
+
+        let mut buf = Vec::new();
+        let m = Complex {
+            repeated_bytes: vec![vec![0; 16]],
+            ..Complex::default()
+        };
+        DelimitedWriter::new(&mut buf).write(&m).unwrap();
+        let mut reader = DelimitedReader::<_, Complex>::new(&buf[..]).with_limit(16);
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().kind(),
+            ErrorKind::TooLarge
+        );
+    }
+
+    #[test]
+    fn rejects_overlong_length_prefixes() {
+        // The tenth byte may only hold the top bit of a u64.
+        let overflow = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+        assert_eq!(read_err(&overflow), ErrorKind::InvalidVarint);
+        assert_eq!(read_err(&[0xff; 11]), ErrorKind::InvalidVarint);
+    }
+
+    #[test]
+    fn round_trips_several_messages() {
+        let messages = vec![
+            Complex::default(),
+            Complex {
+                optional_enum: Some(ComplexEnum::Ten),
+                ..Complex::default()
+            },
+            Complex {
+                repeated_bytes: vec![b"a".to_vec(), b"".to_vec()],
+                ..Complex::default()
+            },
+        ];
+        let mut writer = DelimitedWriter::new(Vec::new());
+        for m in &messages {
+            writer.write(m).unwrap();
+        }
+        let buf = writer.into_inner();
+
+        let read: Vec<Complex> = DelimitedReader::<_, Complex>::new(&buf[..])
+            .map(|m| m.unwrap().absorb().unwrap())
+            .collect();
+        assert_eq!(read, messages);
+
+        let mut r = &buf[..];
+        for m in &messages {
+            let read = read_delimited(&mut r, Complex::descriptor(), DEFAULT_SIZE_LIMIT);
+            assert_eq!(read.unwrap(), Some(m.clone().into()));
+        }
+        assert!(
+            read_delimited(&mut r, Complex::descriptor(), DEFAULT_SIZE_LIMIT)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -1,16 +1,123 @@
+use std::fmt;
+
+use crate::value::{Key, Rule, Value};
+
 #[derive(Debug)]
 pub struct MessageDescriptor {
     pub name: &'static str,
     pub fields: &'static [FieldDescriptor],
 }
 
+impl MessageDescriptor {
+    pub fn field_by_number(&self, number: u32) -> Option<(usize, &FieldDescriptor)> {
+        self.fields
+            .iter()
+            .enumerate()
+            .find(|(_, f)| f.number == number)
+    }
+
+    pub fn field_by_name(&self, name: &str) -> Option<(usize, &FieldDescriptor)> {
+        self.fields.iter().enumerate().find(|(_, f)| f.name == name)
+    }
+}
+
 #[derive(Debug)]
 pub struct FieldDescriptor {
+    pub name: &'static str,
+    pub number: u32,
     pub ty: Type,
     pub label: Label,
+    pub presence: Presence,
+    pub key: Option<Type>,
+    pub message: Option<fn() -> &'static MessageDescriptor>,
+    pub enumeration: Option<fn() -> &'static EnumDescriptor>,
+    pub constraints: &'static [Constraint],
 }
 
-#[derive(Debug)]
+impl FieldDescriptor {
+    pub fn message_descriptor(&self) -> Option<&'static MessageDescriptor> {
+        self.message.map(|descriptor| descriptor())
+    }
+
+    pub fn enum_descriptor(&self) -> Option<&'static EnumDescriptor> {
+        self.enumeration.map(|descriptor| descriptor())
+    }
+
+    pub fn is_map(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn is_repeated(&self) -> bool {
+        self.label == Label::Repeated && self.key.is_none()
+    }
+
+    // Whether an unset field can be told apart from one set to its default value.
+    pub fn has_presence(&self) -> bool {
+        self.presence != Presence::Implicit
+    }
+
+    pub fn matches(&self, value: &Value) -> bool {
+        self.matches_type(value)
+            && match value {
+                Value::Bool(v) => self.matches_rule(v),
+                Value::Bytes(v) => self.matches_rule(v),
+                Value::Enum(v) => self.matches_rule(v),
+                Value::F32(v) => self.matches_rule(v),
+                Value::F64(v) => self.matches_rule(v),
+                Value::I32(v) => self.matches_rule(v),
+                Value::I64(v) => self.matches_rule(v),
+                Value::Message(v) => self.matches_rule(v),
+                Value::String(v) => self.matches_rule(v),
+                Value::U32(v) => self.matches_rule(v),
+                Value::U64(v) => self.matches_rule(v),
+            }
+    }
+
+    // The Value this field holds, in the format of Value::type_string.
+    pub fn type_string(&self) -> String {
+        let rule = match (self.label, self.key) {
+            (_, Some(key)) => format!("Rule::Map(Key::{})", key.variant()),
+            (Label::Repeated, None) => "Rule::Repeated()".to_owned(),
+            _ => "Rule::Singular()".to_owned(),
+        };
+        format!("Value::{}({})", self.ty.variant(), rule)
+    }
+
+    // Whether the value holds elements of this field's type, regardless of cardinality.
+    pub fn matches_type(&self, value: &Value) -> bool {
+        match value {
+            Value::Bool(_) => self.ty == Type::Bool,
+            Value::Bytes(_) => self.ty == Type::Bytes,
+            Value::Enum(_) => self.ty == Type::Enum,
+            Value::F32(_) => self.ty == Type::Float,
+            Value::F64(_) => self.ty == Type::Double,
+            Value::I32(_) => self.ty.is_i32(),
+            Value::I64(_) => self.ty.is_i64(),
+            Value::Message(_) => matches!(self.ty, Type::Message | Type::Group),
+            Value::String(_) => self.ty == Type::String,
+            Value::U32(_) => self.ty.is_u32(),
+            Value::U64(_) => self.ty.is_u64(),
+        }
+    }
+
+    fn matches_rule<T>(&self, rule: &Rule<T>) -> bool {
+        match (rule, self.key) {
+            (Rule::Singular(_), _) => self.label != Label::Repeated,
+            (Rule::Repeated(_), None) => self.label == Label::Repeated,
+            (Rule::Map(k), Some(key)) => match k {
+                Key::Bool(_) => key == Type::Bool,
+                Key::I32(_) => key.is_i32(),
+                Key::I64(_) => key.is_i64(),
+                Key::String(_) => key == Type::String,
+                Key::U32(_) => key.is_u32(),
+                Key::U64(_) => key.is_u64(),
+            },
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Type {
     Double = 1,
     Float = 2,
@@ -32,14 +139,135 @@ pub enum Type {
     SInt64 = 18,
 }
 
-#[derive(Debug)]
+impl Type {
+    // Name of the Value (or Key) variant that holds this type.
+    pub fn variant(self) -> &'static str {
+        match self {
+            Type::Bool => "Bool",
+            Type::Bytes => "Bytes",
+            Type::Enum => "Enum",
+            Type::Float => "F32",
+            Type::Double => "F64",
+            Type::Int32 | Type::SInt32 | Type::SFixed32 => "I32",
+            Type::Int64 | Type::SInt64 | Type::SFixed64 => "I64",
+            Type::Message | Type::Group => "Message",
+            Type::String => "String",
+            Type::UInt32 | Type::Fixed32 => "U32",
+            Type::UInt64 | Type::Fixed64 => "U64",
+        }
+    }
+
+    pub fn is_i32(self) -> bool {
+        matches!(self, Type::Int32 | Type::SInt32 | Type::SFixed32)
+    }
+
+    pub fn is_i64(self) -> bool {
+        matches!(self, Type::Int64 | Type::SInt64 | Type::SFixed64)
+    }
+
+    pub fn is_u32(self) -> bool {
+        matches!(self, Type::UInt32 | Type::Fixed32)
+    }
+
+    pub fn is_u64(self) -> bool {
+        matches!(self, Type::UInt64 | Type::Fixed64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Label {
     Optional = 1,
     Required = 2,
     Repeated = 3,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    // proto3 singular fields and all repeated fields. Default values are never written.
+    Implicit,
+    // proto2 optional, proto3 `optional` and message fields.
+    Explicit,
+    // proto2 required, which must be set for the message to be initialized.
+    LegacyRequired,
+}
+
+// A rule from a field's options that its value must follow beyond its type. Constraints on a
+// repeated or map field apply to each element, except for the ones about the collection itself:
+// MinItems, MaxItems, Unique and RequiredKey. A constraint that does not apply to the field's
+// type is ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constraint {
+    // Length of a string in characters, or of bytes in bytes.
+    MinLen(usize),
+    MaxLen(usize),
+    // A regular expression that a string must match.
+    Pattern(&'static str),
+    // Inclusive bounds on a number.
+    Min(Number),
+    Max(Number),
+    MinItems(usize),
+    MaxItems(usize),
+    // No two elements of a repeated field may be equal.
+    Unique,
+    // A key that a map must contain, in the format of its Display.
+    RequiredKey(&'static str),
+    // An enum value must be one defined by the enum, even if the enum is open.
+    DefinedOnly,
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constraint::MinLen(n) => write!(f, "min_len = {}", n),
+            Constraint::MaxLen(n) => write!(f, "max_len = {}", n),
+            Constraint::Pattern(p) => write!(f, "pattern = {:?}", p),
+            Constraint::Min(n) => write!(f, "min = {}", n),
+            Constraint::Max(n) => write!(f, "max = {}", n),
+            Constraint::MinItems(n) => write!(f, "min_items = {}", n),
+            Constraint::MaxItems(n) => write!(f, "max_items = {}", n),
+            Constraint::Unique => write!(f, "unique"),
+            Constraint::RequiredKey(k) => write!(f, "required_key = {:?}", k),
+            Constraint::DefinedOnly => write!(f, "defined_only"),
+        }
+    }
+}
+
+// A bound for numeric constraints. Integers are kept apart from floats so that 64-bit values
+// compare exactly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Int(n) => write!(f, "{}", n),
+            Number::Float(n) => write!(f, "{}", n),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct EnumDescriptor {
-    pub values: &'static [i32],
+    pub name: &'static str,
+    pub closed: bool,
+    pub values: &'static [EnumValueDescriptor],
+}
+
+impl EnumDescriptor {
+    pub fn value_by_number(&self, number: i32) -> Option<&EnumValueDescriptor> {
+        self.values.iter().find(|v| v.number == number)
+    }
+
+    pub fn value_by_name(&self, name: &str) -> Option<&EnumValueDescriptor> {
+        self.values.iter().find(|v| v.name == name)
+    }
+}
+
+#[derive(Debug)]
+pub struct EnumValueDescriptor {
+    pub name: &'static str,
+    pub number: i32,
 }
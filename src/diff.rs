@@ -0,0 +1,345 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    descriptor::{FieldDescriptor, MessageDescriptor},
+    eq::{self, NanPolicy},
+    error::AbsorbError,
+    validate,
+    value::{Message, Value, ValueRef},
+};
+
+/// The differences between two messages of the same type, in field order.
+#[derive(Debug, Clone)]
+pub struct Diff {
+    pub differences: Vec<Difference>,
+}
+
+/// A single change, to a field or to one element of a repeated or map field.
+#[derive(Debug, Clone)]
+pub struct Difference {
+    /// Where the change is, e.g. `map_message[10].optional_string` or `repeated_bytes[2]`.
+    pub path: String,
+    pub field: &'static FieldDescriptor,
+    pub change: Change,
+}
+
+/// How a value changed. Values are singular: a single element of a repeated or map field, or a
+/// whole singular field.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Added(Value),
+    Removed(Value),
+    Changed { from: Value, to: Value },
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+// One line per difference: `+ path: value`, `- path: value` or `~ path: from -> to`.
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for d in &self.differences {
+            writeln!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let render = |v: &Value| render(validate::element_refs(v)[0].1, self.field);
+        match &self.change {
+            Change::Added(v) => write!(f, "+ {}: {}", self.path, render(v)),
+            Change::Removed(v) => write!(f, "- {}: {}", self.path, render(v)),
+            Change::Changed { from, to } => {
+                write!(f, "~ {}: {} -> {}", self.path, render(from), render(to))
+            }
+        }
+    }
+}
+
+/// Compares two messages field by field. Fields without presence that are unset compare as their
+/// default, nested messages are compared field by field, repeated fields by index and maps by key.
+/// Unknown fields are not compared. The Diff displays one line per change:
+///
+/// ```
+/// use tobu_conversion::{
+///     heavy::complex::{Complex, ComplexEnum},
+///     reflect::Reflect,
+/// };
+///
+/// let a = Complex {
+///     optional_enum: Some(ComplexEnum::Two),
+///     ..Complex::default()
+/// };
+/// let b = Complex {
+///     optional_enum: Some(ComplexEnum::Ten),
+///     repeated_bytes: vec![b"d".to_vec()],
+///     ..Complex::default()
+/// };
+/// let diff = a.reflect().diff(&b.reflect());
+/// assert_eq!(
+///     diff.to_string(),
+///     "~ optional_enum: TWO -> TEN\n+ repeated_bytes[0]: b\"d\"\n"
+/// );
+/// ```
+pub fn diff(a: &Message, b: &Message, d: &MessageDescriptor) -> Result<Diff, AbsorbError> {
+    validate::validate(a, d)?;
+    validate::validate(b, d)?;
+
+    let mut differences = Vec::new();
+    diff_message(a, b, d, "", &mut differences);
+    Ok(Diff { differences })
+}
+
+fn diff_message(
+    a: &Message,
+    b: &Message,
+    d: &MessageDescriptor,
+    prefix: &str,
+    out: &mut Vec<Difference>,
+) {
+    for ((a, b), f) in a.fields.iter().zip(&b.fields).zip(d.fields) {
+        let path = format!("{}{}", prefix, f.name);
+        match (eq::effective(a, f), eq::effective(b, f)) {
+            (Some(a), Some(b)) => diff_value(&a, &b, f, &path, out),
+            (None, Some(b)) => out.push(difference(&path, f, Change::Added(b.into_owned()))),
+            (Some(a), None) => out.push(difference(&path, f, Change::Removed(a.into_owned()))),
+            (None, None) => {}
+        }
+    }
+}
+
+fn diff_value(
+    a: &Value,
+    b: &Value,
+    f: &'static FieldDescriptor,
+    path: &str,
+    out: &mut Vec<Difference>,
+) {
+    let a = validate::element_refs(a);
+    let b = validate::element_refs(b);
+    let at = |key: &Option<String>| match key {
+        Some(key) => format!("{}[{}]", path, key),
+        None => path.to_owned(),
+    };
+
+    if f.is_map() {
+        let keyed: HashMap<_, _> = b.iter().map(|(k, v)| (k, *v)).collect();
+        for (key, x) in &a {
+            match keyed.get(key) {
+                Some(y) => diff_element(*x, *y, f, &at(key), out),
+                None => out.push(difference(&at(key), f, Change::Removed(x.to_value()))),
+            }
+        }
+        let present: HashSet<_> = a.iter().map(|(k, _)| k).collect();
+        for (key, y) in &b {
+            if !present.contains(key) {
+                out.push(difference(&at(key), f, Change::Added(y.to_value())));
+            }
+        }
+        return;
+    }
+
+    for i in 0..a.len().max(b.len()) {
+        match (a.get(i), b.get(i)) {
+            (Some((key, x)), Some((_, y))) => diff_element(*x, *y, f, &at(key), out),
+            (Some((key, x)), None) => {
+                out.push(difference(&at(key), f, Change::Removed(x.to_value())))
+            }
+            (None, Some((key, y))) => {
+                out.push(difference(&at(key), f, Change::Added(y.to_value())))
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+fn diff_element(
+    a: ValueRef,
+    b: ValueRef,
+    f: &'static FieldDescriptor,
+    path: &str,
+    out: &mut Vec<Difference>,
+) {
+    match (a, b, f.message_descriptor()) {
+        (ValueRef::Message(a), ValueRef::Message(b), Some(d)) => {
+            diff_message(a, b, d, &format!("{}.", path), out)
+        }
+        // An unchanged NaN is not reported.
+        (a, b, _) if !eq::same(a, b, NanPolicy::Equal) => out.push(difference(
+            path,
+            f,
+            Change::Changed {
+                from: a.to_value(),
+                to: b.to_value(),
+            },
+        )),
+        _ => {}
+    }
+}
+
+fn difference(path: &str, field: &'static FieldDescriptor, change: Change) -> Difference {
+    Difference {
+        path: path.to_owned(),
+        field,
+        change,
+    }
+}
+
+// A short, human readable form of an element. Messages list their set fields.
+fn render(v: ValueRef, f: &FieldDescriptor) -> String {
+    match v {
+        ValueRef::Bool(v) => v.to_string(),
+        ValueRef::Bytes(v) => format!("b{:?}", String::from_utf8_lossy(v)),
+        ValueRef::Enum(e) => f
+            .enum_descriptor()
+            .and_then(|d| d.value_by_number(e.number))
+            .map_or_else(|| e.number.to_string(), |v| v.name.to_owned()),
+        ValueRef::F32(v) => v.to_string(),
+        ValueRef::F64(v) => v.to_string(),
+        ValueRef::I32(v) => v.to_string(),
+        ValueRef::I64(v) => v.to_string(),
+        ValueRef::Message(m) => match f.message_descriptor() {
+            Some(d) => render_message(m, d),
+            None => "{..}".to_owned(),
+        },
+        ValueRef::String(v) => format!("{:?}", v),
+        ValueRef::U32(v) => v.to_string(),
+        ValueRef::U64(v) => v.to_string(),
+    }
+}
+
+fn render_message(m: &Message, d: &MessageDescriptor) -> String {
+    let fields: Vec<_> = m
+        .fields
+        .iter()
+        .zip(d.fields)
+        .filter_map(|(v, f)| {
+            let v = v.as_ref()?;
+            let elements = validate::element_refs(v);
+            let rendered = match (v.is_singular(), f.is_map()) {
+                (true, _) => render(elements[0].1, f),
+                (false, false) => {
+                    let items: Vec<_> = elements.iter().map(|(_, e)| render(*e, f)).collect();
+                    format!("[{}]", items.join(", "))
+                }
+                (false, true) => {
+                    let entries: Vec<_> = elements
+                        .iter()
+                        .map(|(k, e)| {
+                            format!("{}: {}", k.as_deref().unwrap_or_default(), render(*e, f))
+                        })
+                        .collect();
+                    format!("{{{}}}", entries.join(", "))
+                }
+            };
+            Some(format!("{}: {}", f.name, rendered))
+        })
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        heavy::complex::{Complex, ComplexEnum, ComplexNested},
+        reflect::Reflect,
+    };
+
+    fn nested(s: Option<&str>) -> ComplexNested {
+        ComplexNested {
+            optional_string: s.map(str::to_owned),
+            ..ComplexNested::default()
+        }
+    }
+
+    fn lines(a: Complex, b: Complex) -> Vec<String> {
+        let diff = a.reflect().diff(&b.reflect());
+        diff.differences.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn equal_messages_have_no_differences() {
+        let a = Complex {
+            repeated_bytes: vec![b"a".to_vec()],
+            ..Complex::default()
+        };
+        assert!(a.clone().reflect().diff(&a.reflect()).is_empty());
+    }
+
+    #[test]
+    fn maps_by_key() {
+        let mut a = Complex::default();
+        a.map_message.insert(1, nested(Some("a")));
+        a.map_message.insert(2, nested(Some("b")));
+        a.map_message.insert(3, nested(None));
+        let mut b = Complex::default();
+        b.map_message.insert(4, nested(Some("d")));
+        b.map_message.insert(3, nested(Some("x")));
+        b.map_message.insert(2, nested(Some("c")));
+
+        assert_eq!(
+            lines(a, b),
+            [
+                "- map_message[1]: {optional_string: \"a\"}",
+                "~ map_message[2].optional_string: \"b\" -> \"c\"",
+                "+ map_message[3].optional_string: \"x\"",
+                "+ map_message[4]: {optional_string: \"d\"}",
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_by_index() {
+        let a = Complex {
+            repeated_bytes: vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()],
+            ..Complex::default()
+        };
+        let b = Complex {
+            repeated_bytes: vec![b"a".to_vec(), b"x".to_vec()],
+            ..Complex::default()
+        };
+        assert_eq!(
+            lines(a, b),
+            [
+                "~ repeated_bytes[1]: b\"b\" -> b\"x\"",
+                "- repeated_bytes[2]: b\"c\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_presence() {
+        let set = Complex {
+            optional_enum: Some(ComplexEnum::Two),
+            ..Complex::default()
+        };
+        let diff = set.clone().reflect().diff(&Complex::default().reflect());
+        assert!(matches!(
+            diff.differences.as_slice(),
+            [Difference {
+                change: Change::Removed(_),
+                ..
+            }]
+        ));
+        assert_eq!(diff.to_string(), "- optional_enum: TWO\n");
+
+        let diff = Complex::default().reflect().diff(&set.reflect());
+        assert!(matches!(
+            diff.differences.as_slice(),
+            [Difference {
+                change: Change::Added(_),
+                ..
+            }]
+        ));
+        assert_eq!(diff.to_string(), "+ optional_enum: TWO\n");
+    }
+}
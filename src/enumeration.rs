@@ -0,0 +1,156 @@
+use std::{
+    convert::TryFrom,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+};
+
+use crate::{descriptor::EnumDescriptor, error::AbsorbError, value::Enum};
+
+pub trait ProtoEnum: Copy + Into<Enum> + TryFrom<Enum, Error = AbsorbError> + 'static {
+    fn descriptor() -> &'static EnumDescriptor;
+
+    fn from_i32(number: i32) -> Option<Self>;
+
+    fn values() -> &'static [Self];
+
+    fn from_str_name(name: &str) -> Option<Self> {
+        Self::descriptor()
+            .value_by_name(name)
+            .and_then(|v| Self::from_i32(v.number))
+    }
+
+    fn as_str_name(&self) -> &'static str {
+        Self::descriptor()
+            .value_by_number((*self).into().number)
+            .expect("invariant: every value of a ProtoEnum is in its descriptor")
+            .name
+    }
+}
+
+#[repr(transparent)]
+pub struct EnumOrUnknown<E> {
+    number: i32,
+    _marker: PhantomData<E>,
+}
+
+impl<E: ProtoEnum> EnumOrUnknown<E> {
+    pub fn new(e: E) -> Self {
+        Self::from_i32(e.into().number)
+    }
+
+    pub fn from_i32(number: i32) -> Self {
+        EnumOrUnknown {
+            number,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn value(&self) -> i32 {
+        self.number
+    }
+
+    pub fn enum_value(&self) -> Result<E, i32> {
+        E::from_i32(self.number).ok_or(self.number)
+    }
+
+    pub fn enum_value_or_default(&self) -> E
+    where
+        E: Default,
+    {
+        self.enum_value().unwrap_or_default()
+    }
+}
+
+impl<E: ProtoEnum> Default for EnumOrUnknown<E> {
+    fn default() -> Self {
+        Self::from_i32(0)
+    }
+}
+
+impl<E> Clone for EnumOrUnknown<E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<E> Copy for EnumOrUnknown<E> {}
+
+impl<E> PartialEq for EnumOrUnknown<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.number == other.number
+    }
+}
+
+impl<E> Eq for EnumOrUnknown<E> {}
+
+impl<E> Hash for EnumOrUnknown<E> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.number.hash(state)
+    }
+}
+
+impl<E: ProtoEnum + fmt::Debug> fmt::Debug for EnumOrUnknown<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.enum_value() {
+            Ok(e) => e.fmt(f),
+            Err(number) => write!(f, "Unknown({})", number),
+        }
+    }
+}
+
+impl<E: ProtoEnum> From<E> for EnumOrUnknown<E> {
+    fn from(e: E) -> Self {
+        Self::new(e)
+    }
+}
+
+impl<E> From<Enum> for EnumOrUnknown<E> {
+    fn from(e: Enum) -> Self {
+        EnumOrUnknown {
+            number: e.number,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<E> From<EnumOrUnknown<E>> for Enum {
+    fn from(e: EnumOrUnknown<E>) -> Self {
+        Enum { number: e.number }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heavy::complex::ComplexEnum;
+
+    #[test]
+    fn names() {
+        assert_eq!(ComplexEnum::Ten.as_str_name(), "TEN");
+        assert_eq!(ComplexEnum::from_str_name("TWO"), Some(ComplexEnum::Two));
+        assert_eq!(ComplexEnum::from_str_name("THREE"), None);
+        assert_eq!(
+            ComplexEnum::values(),
+            [ComplexEnum::One, ComplexEnum::Two, ComplexEnum::Ten]
+        );
+    }
+
+    #[test]
+    fn enum_or_unknown() {
+        let known = EnumOrUnknown::new(ComplexEnum::Two);
+        assert_eq!(known.value(), 2);
+        assert_eq!(known.enum_value(), Ok(ComplexEnum::Two));
+        assert_eq!(format!("{:?}", known), "Two");
+
+        let unknown = EnumOrUnknown::<ComplexEnum>::from_i32(7);
+        assert_eq!(unknown.enum_value(), Err(7));
+        assert_eq!(unknown.enum_value_or_default(), ComplexEnum::One);
+        assert_eq!(format!("{:?}", unknown), "Unknown(7)");
+        assert_eq!(Enum::from(unknown).number, 7);
+        assert_eq!(
+            EnumOrUnknown::<ComplexEnum>::from(Enum { number: 7 }),
+            unknown
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::{borrow::Cow, hash::Hash};
+
+use crate::{
+    descriptor::{FieldDescriptor, MessageDescriptor},
+    message::ProtoMessage,
+    value::{Key, Map, Message, Rule, Value, ValueRef, Variant},
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EqOptions {
+    pub nan: NanPolicy,
+    pub ignore_unknown_fields: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NanPolicy {
+    // NaN is unequal to everything, itself included, like f64's PartialEq.
+    #[default]
+    Unequal,
+    // NaN equals NaN, regardless of its bits.
+    Equal,
+}
+
+/// Compares two messages with protobuf semantics: an unset field without presence equals its
+/// default, map entries are compared by key, and nested messages field by field. Messages that do
+/// not fit the descriptor are unequal. Light types implement PartialEq through message_eq instead,
+/// and heavy types compare their fields in place to the same effect. Value and Message derive a
+/// plain structural PartialEq, which knows no descriptor and so none of these rules; it serves
+/// tests that build both sides the same way, and nothing in the crate compares messages with it.
+pub fn equal(a: &Message, b: &Message, d: &MessageDescriptor, options: &EqOptions) -> bool {
+    if a.fields.len() != d.fields.len() || b.fields.len() != d.fields.len() {
+        return false;
+    }
+    if !options.ignore_unknown_fields && a.unknown_fields != b.unknown_fields {
+        return false;
+    }
+
+    a.fields
+        .iter()
+        .zip(&b.fields)
+        .zip(d.fields)
+        .all(|((a, b), f)| match (effective(a, f), effective(b, f)) {
+            (Some(a), Some(b)) => equal_values(&a, &b, f, options),
+            (None, None) => true,
+            _ => false,
+        })
+}
+
+/// The equality behind PartialEq on light types, which borrow their Message rather than build one.
+/// NaN equals NaN, which keeps it reflexive so the types can implement Eq, and agrees with hashing
+/// by fingerprint.
+pub fn message_eq<T: ProtoMessage>(a: &T, b: &T) -> bool {
+    equal(
+        &a.as_message(),
+        &b.as_message(),
+        T::descriptor(),
+        &REFLEXIVE,
+    )
+}
+
+// The options behind every PartialEq built on equal.
+pub(crate) const REFLEXIVE: EqOptions = EqOptions {
+    nan: NanPolicy::Equal,
+    ignore_unknown_fields: false,
+};
+
+// An unset field without presence reads as its default.
+pub(crate) fn effective<'a>(v: &'a Option<Value>, f: &FieldDescriptor) -> Option<Cow<'a, Value>> {
+    match v {
+        Some(v) => Some(Cow::Borrowed(v)),
+        None if !f.has_presence() => Value::empty(f)
+            .or_else(|| Value::default_for(f))
+            .map(Cow::Owned),
+        None => None,
+    }
+}
+
+fn equal_values(a: &Value, b: &Value, f: &FieldDescriptor, options: &EqOptions) -> bool {
+    if !f.matches(a) || !f.matches(b) {
+        return false;
+    }
+
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => equal_rules(a, b, f, options),
+        (Value::Bytes(a), Value::Bytes(b)) => equal_rules(a, b, f, options),
+        (Value::Enum(a), Value::Enum(b)) => equal_rules(a, b, f, options),
+        (Value::F32(a), Value::F32(b)) => equal_rules(a, b, f, options),
+        (Value::F64(a), Value::F64(b)) => equal_rules(a, b, f, options),
+        (Value::I32(a), Value::I32(b)) => equal_rules(a, b, f, options),
+        (Value::I64(a), Value::I64(b)) => equal_rules(a, b, f, options),
+        (Value::Message(a), Value::Message(b)) => equal_rules(a, b, f, options),
+        (Value::String(a), Value::String(b)) => equal_rules(a, b, f, options),
+        (Value::U32(a), Value::U32(b)) => equal_rules(a, b, f, options),
+        (Value::U64(a), Value::U64(b)) => equal_rules(a, b, f, options),
+        _ => false,
+    }
+}
+
+fn equal_rules<T: Variant>(
+    a: &Rule<T>,
+    b: &Rule<T>,
+    f: &FieldDescriptor,
+    options: &EqOptions,
+) -> bool {
+    let eq = |x: &T, y: &T| equal_elements(x.as_ref(), y.as_ref(), f, options);
+    match (a, b) {
+        (Rule::Singular(a), Rule::Singular(b)) => eq(a, b),
+        (Rule::Repeated(a), Rule::Repeated(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| eq(x, y))
+        }
+        (Rule::Map(a), Rule::Map(b)) => match (a, b) {
+            (Key::Bool(a), Key::Bool(b)) => equal_maps(a, b, eq),
+            (Key::I32(a), Key::I32(b)) => equal_maps(a, b, eq),
+            (Key::I64(a), Key::I64(b)) => equal_maps(a, b, eq),
+            (Key::String(a), Key::String(b)) => equal_maps(a, b, eq),
+            (Key::U32(a), Key::U32(b)) => equal_maps(a, b, eq),
+            (Key::U64(a), Key::U64(b)) => equal_maps(a, b, eq),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// Map entries are matched by key, whatever order either side holds them in.
+fn equal_maps<K: Ord + Hash, T>(a: &Map<K, T>, b: &Map<K, T>, eq: impl Fn(&T, &T) -> bool) -> bool {
+    a.len() == b.len() && a.iter().all(|(k, x)| b.get(k).is_some_and(|y| eq(x, y)))
+}
+
+pub(crate) fn equal_elements(
+    a: ValueRef,
+    b: ValueRef,
+    f: &FieldDescriptor,
+    options: &EqOptions,
+) -> bool {
+    match (a, b, f.message_descriptor()) {
+        (ValueRef::Message(a), ValueRef::Message(b), Some(d)) => equal(a, b, d, options),
+        (a, b, _) => same(a, b, options.nan),
+    }
+}
+
+// Equality of single elements other than messages, which need their descriptor to compare and go
+// through equal_elements. A message is never the same as anything here.
+pub(crate) fn same(a: ValueRef, b: ValueRef, nan: NanPolicy) -> bool {
+    let nan = nan == NanPolicy::Equal;
+    match (a, b) {
+        (ValueRef::Bool(a), ValueRef::Bool(b)) => a == b,
+        (ValueRef::Bytes(a), ValueRef::Bytes(b)) => a == b,
+        (ValueRef::Enum(a), ValueRef::Enum(b)) => a == b,
+        (ValueRef::F32(a), ValueRef::F32(b)) => a == b || (nan && a.is_nan() && b.is_nan()),
+        (ValueRef::F64(a), ValueRef::F64(b)) => a == b || (nan && a.is_nan() && b.is_nan()),
+        (ValueRef::I32(a), ValueRef::I32(b)) => a == b,
+        (ValueRef::I64(a), ValueRef::I64(b)) => a == b,
+        (ValueRef::String(a), ValueRef::String(b)) => a == b,
+        (ValueRef::U32(a), ValueRef::U32(b)) => a == b,
+        (ValueRef::U64(a), ValueRef::U64(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        descriptor::{Label, Presence, Type},
+        fingerprint,
+    };
+
+    static FLOAT: MessageDescriptor = MessageDescriptor {
+        name: "Float",
+        fields: &[FieldDescriptor {
+            name: "x",
+            number: 1,
+            ty: Type::Double,
+            label: Label::Optional,
+            presence: Presence::Implicit,
+            key: None,
+            message: None,
+            enumeration: None,
+            constraints: &[],
+        }],
+    };
+
+    fn float(x: f64) -> Message {
+        Message {
+            fields: vec![Some(Value::F64(Rule::Singular(x)))],
+            unknown_fields: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn nan_policy() {
+        let reflexive = EqOptions {
+            nan: NanPolicy::Equal,
+            ..EqOptions::default()
+        };
+        let (a, b) = (float(f64::NAN), float(-f64::NAN));
+        assert!(!equal(&a, &a, &FLOAT, &EqOptions::default()));
+        assert!(equal(&a, &a, &FLOAT, &reflexive));
+        assert!(equal(&a, &b, &FLOAT, &reflexive));
+        assert_eq!(
+            fingerprint::fingerprint(&a, &FLOAT),
+            fingerprint::fingerprint(&b, &FLOAT)
+        );
+
+        // 0.0 and -0.0 are equal either way, and hash alike.
+        let (a, b) = (float(0.0), float(-0.0));
+        assert!(equal(&a, &b, &FLOAT, &EqOptions::default()));
+        assert_eq!(
+            fingerprint::fingerprint(&a, &FLOAT),
+            fingerprint::fingerprint(&b, &FLOAT)
+        );
+    }
+}
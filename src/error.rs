@@ -1,44 +1,621 @@
+use std::{convert::Infallible, fmt::Display, io};
+
 use thiserror::Error;
 
-use crate::value::{Enum, Value};
+use crate::{
+    descriptor::{Constraint, FieldDescriptor, Type},
+    value::{Enum, Value},
+    wire::WireType,
+};
 
+/// Why a message does not fit its descriptor or concrete type. Errors carry the path to where the
+/// problem was found, such as `map_message[10].optional_string`, and type errors both the expected
+/// and the found type.
 #[derive(Debug, Error)]
 pub enum AbsorbError {
-    #[error("{value} is not a valid variant of {name}")]
-    InvalidEnum { name: String, value: i32 },
+    #[error("{value} is not a valid variant of {name}{}", at(path))]
+    InvalidEnum {
+        path: String,
+        name: String,
+        value: i32,
+    },
+
+    #[error(
+        "Expected {expected} fields, but message{} contains {actual} fields",
+        at(path)
+    )]
+    InvalidLength {
+        path: String,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("Field {path} expected {expected}, but found {actual}")]
+    InvalidType {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Field {path} is not optional")]
+    TypeNotOptional { path: String },
+
+    #[error("Index {index} is out of bounds for field {path} of length {len}")]
+    OutOfBounds {
+        path: String,
+        index: usize,
+        len: usize,
+    },
+
+    #[error("Missing required fields {}", fields.join(", "))]
+    MissingRequired { fields: Vec<String> },
 
-    #[error("Expected {expected} fields, but message contains {actual} fields")]
-    InvalidLength { expected: usize, actual: usize },
+    #[error("Field {field} of {from} is not compatible with {to}")]
+    Incompatible {
+        field: String,
+        from: String,
+        to: String,
+    },
 
-    #[error("Field type {ty} does not match type of field {name}")]
-    InvalidType { name: String, ty: String },
+    #[error("{}", errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Multiple { errors: Vec<AbsorbError> },
 
-    #[error("Field {name} is not optional")]
-    TypeNotOptional { name: String },
+    #[error(transparent)]
+    Descriptor(#[from] DescriptorError),
+}
+
+fn at(path: &str) -> String {
+    match path {
+        "" => String::new(),
+        path => format!(" at {}", path),
+    }
+}
+
+impl From<Infallible> for AbsorbError {
+    fn from(e: Infallible) -> Self {
+        match e {}
+    }
 }
 
 impl AbsorbError {
     pub fn invalid_enum(name: &str, enumeration: &Enum) -> Self {
         Self::InvalidEnum {
+            path: String::new(),
             name: name.to_string(),
             value: enumeration.number,
         }
     }
 
     pub fn invalid_length(expected: usize, actual: usize) -> Self {
-        Self::InvalidLength { expected, actual }
+        Self::InvalidLength {
+            path: String::new(),
+            expected,
+            actual,
+        }
     }
 
-    pub fn invalid_type(name: &str, value: &Value) -> Self {
+    pub fn invalid_type(field: &FieldDescriptor, value: &Value) -> Self {
         Self::InvalidType {
-            name: name.to_string(),
-            ty: value.type_string(),
+            path: field.name.to_string(),
+            expected: field.type_string(),
+            actual: value.type_string(),
+        }
+    }
+
+    // A single element of a repeated or map field that does not match the field type.
+    pub fn invalid_element(field: &FieldDescriptor, value: &Value) -> Self {
+        Self::InvalidType {
+            path: field.name.to_string(),
+            expected: format!("Value::{}(Rule::Singular())", field.ty.variant()),
+            actual: value.type_string(),
+        }
+    }
+
+    // A map key that does not match the key type of the field.
+    pub fn invalid_key(field: &FieldDescriptor, key: &Value) -> Self {
+        Self::InvalidType {
+            path: field.name.to_string(),
+            expected: match field.key {
+                Some(key) => format!("Value::{}(Rule::Singular())", key.variant()),
+                None => "no key".to_owned(),
+            },
+            actual: key.type_string(),
+        }
+    }
+
+    pub fn out_of_bounds(field: &FieldDescriptor, index: usize, len: usize) -> Self {
+        Self::OutOfBounds {
+            path: field.name.to_string(),
+            index,
+            len,
         }
     }
 
     pub fn not_optional(name: &str) -> Self {
         Self::TypeNotOptional {
+            path: name.to_string(),
+        }
+    }
+
+    pub fn missing_required(fields: Vec<String>) -> Self {
+        Self::MissingRequired { fields }
+    }
+
+    pub fn unknown_field(message: &str, field: &str) -> Self {
+        Self::Descriptor(DescriptorError::unknown_field(message, field))
+    }
+
+    pub fn missing_descriptor(name: &str) -> Self {
+        Self::Descriptor(DescriptorError::missing_descriptor(name))
+    }
+
+    pub fn incompatible(field: &str, from: &str, to: &str) -> Self {
+        Self::Incompatible {
+            field: field.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    // Combines errors into one report, or None if there are none.
+    pub fn multiple(mut errors: Vec<AbsorbError>) -> Option<Self> {
+        match errors.len() {
+            0 => None,
+            1 => errors.pop(),
+            _ => Some(Self::Multiple { errors }),
+        }
+    }
+
+    // Places the error inside the given field of an enclosing message.
+    pub fn at(self, field: &str) -> Self {
+        self.within(field)
+    }
+
+    // Places the error inside an element of a repeated or map field of an enclosing message.
+    pub fn at_key(self, field: &str, key: impl Display) -> Self {
+        self.within(&format!("{}[{}]", field, key))
+    }
+
+    fn within(mut self, prefix: &str) -> Self {
+        let join = |path: &mut String| {
+            *path = match path.as_str() {
+                "" => prefix.to_string(),
+                _ => format!("{}.{}", prefix, path),
+            }
+        };
+        match &mut self {
+            Self::InvalidEnum { path, .. }
+            | Self::InvalidLength { path, .. }
+            | Self::InvalidType { path, .. }
+            | Self::TypeNotOptional { path }
+            | Self::OutOfBounds { path, .. } => join(path),
+            Self::MissingRequired { fields } => fields.iter_mut().for_each(join),
+            Self::Multiple { errors } => {
+                *errors = errors.drain(..).map(|e| e.within(prefix)).collect()
+            }
+            Self::Incompatible { .. } | Self::Descriptor(_) => {}
+        }
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::InvalidEnum { .. } => ErrorKind::InvalidEnum,
+            Self::InvalidLength { .. } => ErrorKind::InvalidLength,
+            Self::InvalidType { .. } => ErrorKind::InvalidType,
+            Self::TypeNotOptional { .. } => ErrorKind::NotOptional,
+            Self::OutOfBounds { .. } => ErrorKind::OutOfBounds,
+            Self::MissingRequired { .. } => ErrorKind::MissingRequired,
+            Self::Incompatible { .. } => ErrorKind::Incompatible,
+            Self::Multiple { .. } => ErrorKind::Multiple,
+            Self::Descriptor(e) => e.kind(),
+        }
+    }
+
+    pub fn errors(&self) -> &[AbsorbError] {
+        match self {
+            Self::Multiple { errors } => errors,
+            e => std::slice::from_ref(e),
+        }
+    }
+
+    pub fn into_errors(self) -> Vec<AbsorbError> {
+        match self {
+            Self::Multiple { errors } => errors,
+            e => vec![e],
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("Unexpected end of input")]
+    Truncated,
+
+    #[error("Varint exceeds 64 bits")]
+    InvalidVarint,
+
+    #[error("{0} is not a valid wire type")]
+    InvalidWireType(u64),
+
+    #[error("{0} is not a valid field number")]
+    InvalidFieldNumber(u64),
+
+    #[error("Field {name} cannot be decoded from wire type {wire_type:?}")]
+    WireTypeMismatch { name: String, wire_type: WireType },
+
+    #[error("Field {name} contains invalid UTF-8")]
+    InvalidUtf8 { name: String },
+
+    #[error("Unexpected end of group {0}")]
+    UnexpectedEndGroup(u32),
+
+    #[error("Message nesting exceeds recursion limit of {0}")]
+    RecursionLimit(usize),
+
+    #[error(transparent)]
+    Descriptor(#[from] DescriptorError),
+
+    #[error(transparent)]
+    Absorb(#[from] AbsorbError),
+}
+
+impl DecodeError {
+    pub fn wire_type_mismatch(name: &str, wire_type: WireType) -> Self {
+        Self::WireTypeMismatch {
+            name: name.to_string(),
+            wire_type,
+        }
+    }
+
+    pub fn invalid_utf8(name: &str) -> Self {
+        Self::InvalidUtf8 {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn missing_descriptor(name: &str) -> Self {
+        Self::Descriptor(DescriptorError::missing_descriptor(name))
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Truncated => ErrorKind::Truncated,
+            Self::InvalidVarint => ErrorKind::InvalidVarint,
+            Self::InvalidWireType(_) => ErrorKind::InvalidWireType,
+            Self::InvalidFieldNumber(_) => ErrorKind::InvalidFieldNumber,
+            Self::WireTypeMismatch { .. } => ErrorKind::WireTypeMismatch,
+            Self::InvalidUtf8 { .. } => ErrorKind::InvalidUtf8,
+            Self::UnexpectedEndGroup(_) => ErrorKind::UnexpectedEndGroup,
+            Self::RecursionLimit(_) => ErrorKind::RecursionLimit,
+            Self::Descriptor(e) => e.kind(),
+            Self::Absorb(e) => e.kind(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum EncodeError {
+    #[error(
+        "Expected {expected} fields, but message{} contains {actual} fields",
+        at(path)
+    )]
+    InvalidLength {
+        path: String,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("Field {path} expected {expected}, but found {actual}")]
+    InvalidType {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error(transparent)]
+    Descriptor(#[from] DescriptorError),
+}
+
+impl EncodeError {
+    pub fn invalid_length(expected: usize, actual: usize) -> Self {
+        Self::InvalidLength {
+            path: String::new(),
+            expected,
+            actual,
+        }
+    }
+
+    pub fn invalid_type(field: &FieldDescriptor, value: &Value) -> Self {
+        Self::InvalidType {
+            path: field.name.to_string(),
+            expected: field.type_string(),
+            actual: value.type_string(),
+        }
+    }
+
+    pub fn missing_descriptor(name: &str) -> Self {
+        Self::Descriptor(DescriptorError::missing_descriptor(name))
+    }
+
+    // Places the error inside the given field of an enclosing message.
+    pub fn at(self, field: &str) -> Self {
+        self.within(field)
+    }
+
+    // Places the error inside an element of a repeated or map field of an enclosing message.
+    pub fn at_key(self, field: &str, key: impl Display) -> Self {
+        self.within(&format!("{}[{}]", field, key))
+    }
+
+    fn within(mut self, prefix: &str) -> Self {
+        if let Self::InvalidLength { path, .. } | Self::InvalidType { path, .. } = &mut self {
+            *path = match path.as_str() {
+                "" => prefix.to_string(),
+                _ => format!("{}.{}", prefix, path),
+            };
+        }
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::InvalidLength { .. } => ErrorKind::InvalidLength,
+            Self::InvalidType { .. } => ErrorKind::InvalidType,
+            Self::Descriptor(e) => e.kind(),
+        }
+    }
+}
+
+// Failures to resolve a field or a nested descriptor, shared by every codec and by reflection.
+#[derive(Debug, Error)]
+pub enum DescriptorError {
+    #[error("Field {name} has no descriptor for its type")]
+    MissingDescriptor { name: String },
+
+    #[error("Message {message} has no field {field}")]
+    UnknownField { message: String, field: String },
+
+    #[error("Path {path:?} is empty or does not lead through singular message fields")]
+    InvalidPath { path: String },
+
+    #[error("Field {field} is a map keyed by {key:?}, which cannot key a map")]
+    InvalidKey { field: String, key: Type },
+
+    #[error("Field {field} has an invalid pattern {pattern:?}: {reason}")]
+    InvalidPattern {
+        field: String,
+        pattern: String,
+        reason: String,
+    },
+}
+
+impl DescriptorError {
+    pub fn missing_descriptor(name: &str) -> Self {
+        Self::MissingDescriptor {
             name: name.to_string(),
         }
     }
+
+    pub fn unknown_field(message: &str, field: &str) -> Self {
+        Self::UnknownField {
+            message: message.to_string(),
+            field: field.to_string(),
+        }
+    }
+
+    pub fn invalid_path(path: &str) -> Self {
+        Self::InvalidPath {
+            path: path.to_string(),
+        }
+    }
+
+    pub fn invalid_key(field: &str, key: Type) -> Self {
+        Self::InvalidKey {
+            field: field.to_string(),
+            key,
+        }
+    }
+
+    pub fn invalid_pattern(field: &str, pattern: &str, reason: impl Display) -> Self {
+        Self::InvalidPattern {
+            field: field.to_string(),
+            pattern: pattern.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::MissingDescriptor { .. } => ErrorKind::MissingDescriptor,
+            Self::UnknownField { .. } => ErrorKind::UnknownField,
+            Self::InvalidPath { .. } => ErrorKind::InvalidPath,
+            Self::InvalidKey { .. } => ErrorKind::InvalidKey,
+            Self::InvalidPattern { .. } => ErrorKind::InvalidPattern,
+        }
+    }
+}
+
+// A field whose value breaks one of the constraints in its descriptor.
+#[derive(Debug, Error)]
+#[error("Field {path} violates {constraint}")]
+pub struct Violation {
+    pub path: String,
+    pub constraint: Constraint,
+}
+
+#[derive(Debug, Error)]
+pub enum ConstraintError {
+    #[error("{}", violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Violations { violations: Vec<Violation> },
+
+    #[error(transparent)]
+    Descriptor(#[from] DescriptorError),
+}
+
+impl ConstraintError {
+    pub fn violations(&self) -> &[Violation] {
+        match self {
+            Self::Violations { violations } => violations,
+            Self::Descriptor(_) => &[],
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Violations { .. } => ErrorKind::Constraint,
+            Self::Descriptor(e) => e.kind(),
+        }
+    }
+}
+
+// Why testing::Matcher::check failed: the messages differ, or one of its paths does not fit the
+// descriptor.
+#[derive(Debug, Error)]
+pub enum MatchError {
+    #[error("assertion failed: `left == right` for {message}\n{report}")]
+    Mismatch { message: String, report: String },
+
+    #[error(transparent)]
+    Descriptor(#[from] DescriptorError),
+}
+
+impl MatchError {
+    pub fn mismatch(message: &str, report: String) -> Self {
+        Self::Mismatch {
+            message: message.to_string(),
+            report,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Mismatch { .. } => ErrorKind::Mismatch,
+            Self::Descriptor(e) => e.kind(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StreamError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("Message of {len} bytes exceeds the limit of {limit} bytes")]
+    TooLarge { len: u64, limit: usize },
+
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+}
+
+impl StreamError {
+    pub fn too_large(len: u64, limit: usize) -> Self {
+        Self::TooLarge { len, limit }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Io(_) => ErrorKind::Io,
+            Self::TooLarge { .. } => ErrorKind::TooLarge,
+            Self::Decode(e) => e.kind(),
+            Self::Encode(e) => e.kind(),
+        }
+    }
+}
+
+/// Any error the crate produces. Each module returns its own error type, and all of them convert
+/// into this one.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+
+    #[error(transparent)]
+    Absorb(#[from] AbsorbError),
+
+    #[error(transparent)]
+    Descriptor(#[from] DescriptorError),
+
+    #[error(transparent)]
+    Constraint(#[from] ConstraintError),
+
+    #[error(transparent)]
+    Stream(#[from] StreamError),
+
+    #[error(transparent)]
+    Match(#[from] MatchError),
+}
+
+impl From<Infallible> for Error {
+    fn from(e: Infallible) -> Self {
+        match e {}
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Decode(e) => e.kind(),
+            Self::Encode(e) => e.kind(),
+            Self::Absorb(e) => e.kind(),
+            Self::Descriptor(e) => e.kind(),
+            Self::Constraint(e) => e.kind(),
+            Self::Stream(e) => e.kind(),
+            Self::Match(e) => e.kind(),
+        }
+    }
+}
+
+/// A stable classification of errors to match on, independent of which error type carries them
+/// and of the details in it. New kinds may be added.
+///
+/// ```
+/// use tobu_conversion::{error::ErrorKind, heavy::simple::Simple, message::ProtoMessage};
+///
+/// let e = Simple::decode(&[0x08]).unwrap_err();
+/// assert_eq!(e.kind(), ErrorKind::Truncated);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    // Wire format
+    Truncated,
+    InvalidVarint,
+    InvalidWireType,
+    InvalidFieldNumber,
+    WireTypeMismatch,
+    InvalidUtf8,
+    UnexpectedEndGroup,
+    RecursionLimit,
+
+    // Message contents
+    InvalidEnum,
+    InvalidLength,
+    InvalidType,
+    NotOptional,
+    OutOfBounds,
+    MissingRequired,
+    Incompatible,
+    Multiple,
+    Constraint,
+
+    // Descriptors
+    MissingDescriptor,
+    UnknownField,
+    InvalidPath,
+    InvalidKey,
+    InvalidPattern,
+
+    // Streams
+    Io,
+    TooLarge,
+
+    // Testing
+    Mismatch,
 }
@@ -0,0 +1,421 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    descriptor::{Label, MessageDescriptor, Type},
+    error::{AbsorbError, DescriptorError},
+    value::{self, Message, Rule, Value},
+};
+
+/// A set of field paths such as `optional_enum` or `nested.list`, as in google.protobuf.FieldMask.
+/// Every segment but the last must name a singular message field. `prune` and `merge_with_mask`
+/// apply a mask to messages, and are also available on `Reflection`.
+///
+/// ```
+/// use tobu_conversion::{
+///     field_mask::FieldMask,
+///     heavy::complex::{Complex, ComplexEnum},
+///     reflect::Reflect,
+/// };
+///
+/// let mask = FieldMask::parse("optional_enum").unwrap();
+/// let mut m = Complex {
+///     optional_enum: Some(ComplexEnum::Two),
+///     repeated_bytes: vec![b"a".to_vec()],
+///     ..Complex::default()
+/// }
+/// .reflect();
+/// m.prune(&mask).unwrap();
+/// assert!(m.absorb().unwrap().repeated_bytes.is_empty());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldMask {
+    paths: Vec<String>,
+}
+
+impl FieldMask {
+    pub fn new<S: Into<String>>(paths: impl IntoIterator<Item = S>) -> Self {
+        FieldMask {
+            paths: paths.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    // Parses comma separated paths, e.g. `map_message,nested.name`. An empty string is the empty
+    // mask, as in the JSON form of FieldMask; empty paths and empty segments, as in `a,` or
+    // `a..b`, are rejected.
+    pub fn parse(s: &str) -> Result<Self, DescriptorError> {
+        if s.trim().is_empty() {
+            return Ok(FieldMask::default());
+        }
+        let paths = s
+            .split(',')
+            .map(str::trim)
+            .map(|p| match p.split('.').any(str::is_empty) {
+                true => Err(DescriptorError::invalid_path(p)),
+                false => Ok(p),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(paths))
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    pub fn validate(&self, d: &MessageDescriptor) -> Result<(), DescriptorError> {
+        self.tree(d).map(|_| ())
+    }
+
+    // Sorted and without duplicates or paths covered by another path, e.g. `a.b` when `a` is
+    // also in the mask.
+    pub fn canonical(&self) -> FieldMask {
+        let mut paths = self.paths.clone();
+        paths.sort();
+        paths.dedup();
+
+        let mut canonical: Vec<String> = Vec::new();
+        for path in paths {
+            // In sorted order a path comes right after the paths covering it.
+            if !canonical.last().is_some_and(|prefix| covers(prefix, &path)) {
+                canonical.push(path);
+            }
+        }
+        FieldMask { paths: canonical }
+    }
+
+    pub fn union(&self, other: &FieldMask) -> FieldMask {
+        let paths = self.paths.iter().chain(&other.paths).cloned().collect();
+        FieldMask { paths }.canonical()
+    }
+
+    pub fn intersection(&self, other: &FieldMask) -> FieldMask {
+        let mut paths = Vec::new();
+        for a in &self.paths {
+            for b in &other.paths {
+                if covers(a, b) {
+                    paths.push(b.clone());
+                } else if covers(b, a) {
+                    paths.push(a.clone());
+                }
+            }
+        }
+        FieldMask { paths }.canonical()
+    }
+
+    fn tree(&self, d: &MessageDescriptor) -> Result<Tree, DescriptorError> {
+        let mut root = Tree::default();
+        for path in &self.canonical().paths {
+            let mut node = &mut root;
+            let mut d = d;
+            let mut segments = path.split('.').peekable();
+            while let Some(segment) = segments.next() {
+                let (i, f) = d
+                    .field_by_name(segment)
+                    .ok_or_else(|| DescriptorError::unknown_field(d.name, segment))?;
+                node = node.children.entry(i).or_default();
+                if segments.peek().is_none() {
+                    break;
+                }
+                d = match f.message_descriptor() {
+                    Some(d) if f.label != Label::Repeated => d,
+                    _ => return Err(DescriptorError::invalid_path(path)),
+                };
+            }
+        }
+        Ok(root)
+    }
+}
+
+impl fmt::Display for FieldMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.paths.join(","))
+    }
+}
+
+// Whether path is prefix itself or a field nested inside it.
+fn covers(prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('.'),
+        None => false,
+    }
+}
+
+const PATH: &str = "invariant: a mask only descends into message fields";
+
+// The paths of a mask by field index. A node without children covers its whole field.
+#[derive(Default)]
+struct Tree {
+    children: BTreeMap<usize, Tree>,
+}
+
+/// Clears every field of the message that is not in the mask, along with unknown fields. On error
+/// the message is left as it was.
+pub fn prune(m: &mut Message, d: &MessageDescriptor, mask: &FieldMask) -> Result<(), AbsorbError> {
+    let tree = mask.tree(d)?;
+    check_prunable(m, d, &tree)?;
+    prune_message(m, d, &tree);
+    Ok(())
+}
+
+// Checks every message along the paths of the mask before prune_message changes any of them.
+fn check_prunable(m: &Message, d: &MessageDescriptor, tree: &Tree) -> Result<(), AbsorbError> {
+    if m.fields.len() != d.fields.len() {
+        return Err(AbsorbError::invalid_length(d.fields.len(), m.fields.len()));
+    }
+
+    for (&i, node) in &tree.children {
+        let f = &d.fields[i];
+        match &m.fields[i] {
+            _ if node.children.is_empty() => {}
+            Some(Value::Message(Rule::Singular(m))) => {
+                let d = f.message_descriptor().expect(PATH);
+                check_prunable(m, d, node).map_err(|e| e.at(f.name))?;
+            }
+            Some(v) => return Err(AbsorbError::invalid_type(f, v)),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn prune_message(m: &mut Message, d: &MessageDescriptor, tree: &Tree) {
+    for (i, (v, f)) in m.fields.iter_mut().zip(d.fields).enumerate() {
+        match (tree.children.get(&i), v) {
+            (Some(node), Some(Value::Message(Rule::Singular(m)))) if !node.children.is_empty() => {
+                prune_message(m, f.message_descriptor().expect(PATH), node)
+            }
+            (Some(_), _) => {}
+            (None, v) => *v = Value::empty(f),
+        }
+    }
+    m.unknown_fields.clear();
+}
+
+/// Merges the fields of src that are in the mask into dst, like FieldMaskUtil.merge in Java.
+/// Masked singular fields are copied even when src holds the default, so a mask can reset a
+/// field; only an unset field with presence leaves dst alone. Messages merge recursively and
+/// repeated and map fields append. Messages along a path are created in dst as needed.
+pub fn merge_with_mask(
+    dst: &mut Message,
+    src: &Message,
+    d: &MessageDescriptor,
+    mask: &FieldMask,
+) -> Result<(), AbsorbError> {
+    merge_message(dst, src, d, &mask.tree(d)?)
+}
+
+fn merge_message(
+    dst: &mut Message,
+    src: &Message,
+    d: &MessageDescriptor,
+    tree: &Tree,
+) -> Result<(), AbsorbError> {
+    for m in [&*dst, src] {
+        if m.fields.len() != d.fields.len() {
+            return Err(AbsorbError::invalid_length(d.fields.len(), m.fields.len()));
+        }
+    }
+
+    for (&i, node) in &tree.children {
+        let f = &d.fields[i];
+        if node.children.is_empty() {
+            match &src.fields[i] {
+                _ if f.label == Label::Repeated || matches!(f.ty, Type::Message | Type::Group) => {
+                    value::merge_field(&mut dst.fields[i], &src.fields[i], f)?
+                }
+                None if f.has_presence() => {}
+                v => dst.fields[i] = v.clone(),
+            }
+            continue;
+        }
+
+        let src = match &src.fields[i] {
+            Some(Value::Message(Rule::Singular(m))) => m,
+            Some(v) => return Err(AbsorbError::invalid_type(f, v)),
+            None => continue,
+        };
+        let d = f.message_descriptor().expect(PATH);
+        let dst = match dst.fields[i]
+            .get_or_insert_with(|| Value::Message(Rule::Singular(Message::new(d))))
+        {
+            Value::Message(Rule::Singular(m)) => m,
+            v => return Err(AbsorbError::invalid_type(f, v)),
+        };
+        merge_message(dst, src, d, node).map_err(|e| e.at(f.name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        descriptor::{FieldDescriptor, Presence},
+        error::ErrorKind,
+        heavy::{
+            complex::{Complex, ComplexEnum, ComplexNested},
+            simple::Simple,
+        },
+        message::ProtoMessage,
+        reflect::Reflect,
+    };
+
+    fn mask(s: &str) -> FieldMask {
+        FieldMask::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(mask("a, b.c").paths(), ["a", "b.c"]);
+        assert!(mask("").is_empty());
+        for s in ["a,", ",a", "a,,b", "a..b", "a.", ".a"] {
+            let e = FieldMask::parse(s).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidPath, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let d = Complex::descriptor();
+        assert!(mask("optional_enum,map_message").validate(d).is_ok());
+        let e = mask("nope").validate(d).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnknownField);
+        // Paths cannot go through maps or scalars.
+        for s in ["map_message.optional_string", "optional_enum.x"] {
+            let e = mask(s).validate(d).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidPath, "{}", s);
+        }
+    }
+
+    #[test]
+    fn combine() {
+        let a = mask("b.c,a,b.c,a.x");
+        assert_eq!(a.canonical().paths(), ["a", "b.c"]);
+        assert_eq!(a.union(&mask("b")).paths(), ["a", "b"]);
+        assert_eq!(a.intersection(&mask("a.y,b")).paths(), ["a.y", "b.c"]);
+    }
+
+    #[test]
+    fn merge_resets_implicit_fields() {
+        let mut dst = Simple {
+            simple_bool: true,
+            ..Simple::default()
+        }
+        .reflect();
+        dst.merge_with_mask(&Simple::default().reflect(), &mask("simple_bool"))
+            .unwrap();
+        assert!(!dst.absorb().unwrap().simple_bool);
+    }
+
+    #[test]
+    fn merge_copies_set_fields_and_appends_collections() {
+        let dst = Complex {
+            optional_enum: Some(ComplexEnum::Ten),
+            repeated_bytes: vec![b"a".to_vec()],
+            ..Complex::default()
+        };
+        let mut src = Complex {
+            repeated_bytes: vec![b"b".to_vec()],
+            ..Complex::default()
+        };
+        src.map_message.insert(1, ComplexNested::default());
+
+        // An unset field with presence leaves dst alone.
+        let mut m = dst.clone().reflect();
+        m.merge_with_mask(
+            &src.clone().reflect(),
+            &mask("optional_enum,repeated_bytes"),
+        )
+        .unwrap();
+        let m = m.absorb().unwrap();
+        assert_eq!(m.optional_enum, Some(ComplexEnum::Ten));
+        assert_eq!(m.repeated_bytes, [b"a".to_vec(), b"b".to_vec()]);
+        assert!(m.map_message.is_empty());
+
+        src.optional_enum = Some(ComplexEnum::One);
+        let mut m = dst.reflect();
+        m.merge_with_mask(&src.reflect(), &mask("optional_enum"))
+            .unwrap();
+        assert_eq!(m.absorb().unwrap().optional_enum, Some(ComplexEnum::One));
+    }
+
+    #[test]
+    fn prune_clears_unmasked_and_unknown_fields() {
+        let mut m = Complex {
+            optional_enum: Some(ComplexEnum::Two),
+            repeated_bytes: vec![b"a".to_vec()],
+            unknown_fields: vec![0x78, 0x01],
+            ..Complex::default()
+        }
+        .reflect();
+        m.prune(&mask("optional_enum")).unwrap();
+        assert_eq!(
+            m.absorb().unwrap(),
+            Complex {
+                optional_enum: Some(ComplexEnum::Two),
+                ..Complex::default()
+            }
+        );
+    }
+
+    // Holds a number and a nested message, so a mask can reach into a singular message field.
+    static OUTER: MessageDescriptor = MessageDescriptor {
+        name: "Outer",
+        fields: &[
+            FieldDescriptor {
+                name: "number",
+                number: 1,
+                ty: Type::Int32,
+                label: Label::Optional,
+                presence: Presence::Implicit,
+                key: None,
+                message: None,
+                enumeration: None,
+                constraints: &[],
+            },
+            FieldDescriptor {
+                name: "nested",
+                number: 2,
+                ty: Type::Message,
+                label: Label::Optional,
+                presence: Presence::Explicit,
+                key: None,
+                message: Some(ComplexNested::descriptor),
+                enumeration: None,
+                constraints: &[],
+            },
+        ],
+    };
+
+    #[test]
+    fn prune_leaves_the_message_alone_on_error() {
+        let outer = |nested: Value| Message {
+            fields: vec![Some(Value::I32(Rule::Singular(1))), Some(nested)],
+            unknown_fields: vec![0x78, 0x01],
+        };
+        let mask = mask("nested.optional_string");
+
+        let mut m = outer(Value::I32(Rule::Singular(2)));
+        let e = prune(&mut m, &OUTER, &mask).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidType);
+        assert_eq!(m, outer(Value::I32(Rule::Singular(2))));
+
+        let short = Value::Message(Rule::Singular(Message {
+            fields: Vec::new(),
+            unknown_fields: Vec::new(),
+        }));
+        let mut m = outer(short.clone());
+        let e = prune(&mut m, &OUTER, &mask).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidLength);
+        assert_eq!(m, outer(short));
+
+        let nested = Value::Message(Rule::Singular(ComplexNested::default().into()));
+        let mut m = outer(nested.clone());
+        prune(&mut m, &OUTER, &mask).unwrap();
+        assert_eq!(m.fields[0], Value::empty(&OUTER.fields[0]));
+        assert!(m.unknown_fields.is_empty());
+    }
+}
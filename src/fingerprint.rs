@@ -0,0 +1,189 @@
+use crate::{
+    descriptor::MessageDescriptor,
+    eq, validate,
+    value::{Message, ValueRef},
+};
+
+/// A 128-bit FNV-1a hash of the contents of a message, stable across processes and platforms, so
+/// it can key caches and deduplicate messages across runs. Messages equal under eq::equal with the
+/// default options have the same fingerprint: unset fields without presence hash as their
+/// default, and map entries in key order. Floats hash by value, so 0.0 and -0.0 match and every
+/// NaN is the same. Light types implement Hash with it.
+pub fn fingerprint(m: &Message, d: &MessageDescriptor) -> u128 {
+    let mut h = Fnv::new();
+    hash_message(&mut h, m, d);
+    h.0
+}
+
+fn hash_message(h: &mut Fnv, m: &Message, d: &MessageDescriptor) {
+    for (v, f) in m.fields.iter().zip(d.fields) {
+        let v = match eq::effective(v, f) {
+            Some(v) => v,
+            None => continue,
+        };
+        let elements = validate::element_refs(&v);
+        h.write(&f.number.to_le_bytes());
+        h.write(&(elements.len() as u64).to_le_bytes());
+        for (key, e) in elements {
+            if let Some(key) = key {
+                h.write_bytes(key.as_bytes());
+            }
+            hash_element(h, e, f.message_descriptor());
+        }
+    }
+    // Field numbers start at 1, so 0 ends the fields.
+    h.write(&0u32.to_le_bytes());
+    h.write_bytes(&m.unknown_fields);
+}
+
+fn hash_element(h: &mut Fnv, e: ValueRef, d: Option<&MessageDescriptor>) {
+    match e {
+        ValueRef::Bool(v) => h.write(&[0, v as u8]),
+        ValueRef::Bytes(v) => {
+            h.write(&[1]);
+            h.write_bytes(v);
+        }
+        ValueRef::Enum(v) => {
+            h.write(&[2]);
+            h.write(&v.number.to_le_bytes());
+        }
+        ValueRef::F32(v) => {
+            h.write(&[3]);
+            h.write(&normalize(v.into()).to_le_bytes());
+        }
+        ValueRef::F64(v) => {
+            h.write(&[4]);
+            h.write(&normalize(v).to_le_bytes());
+        }
+        ValueRef::I32(v) => {
+            h.write(&[5]);
+            h.write(&v.to_le_bytes());
+        }
+        ValueRef::I64(v) => {
+            h.write(&[6]);
+            h.write(&v.to_le_bytes());
+        }
+        ValueRef::Message(m) => {
+            h.write(&[7]);
+            if let Some(d) = d {
+                hash_message(h, m, d);
+            }
+        }
+        ValueRef::String(v) => {
+            h.write(&[8]);
+            h.write_bytes(v.as_bytes());
+        }
+        ValueRef::U32(v) => {
+            h.write(&[9]);
+            h.write(&v.to_le_bytes());
+        }
+        ValueRef::U64(v) => {
+            h.write(&[10]);
+            h.write(&v.to_le_bytes());
+        }
+    }
+}
+
+fn normalize(v: f64) -> u64 {
+    if v.is_nan() {
+        f64::NAN.to_bits()
+    } else if v == 0.0 {
+        0
+    } else {
+        v.to_bits()
+    }
+}
+
+struct Fnv(u128);
+
+impl Fnv {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    fn new() -> Self {
+        Fnv(Self::OFFSET)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u128::from(*b);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    // Length prefixed, so adjacent strings cannot run into each other.
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        heavy::complex::{Complex, ComplexEnum, ComplexNested},
+        light,
+        message::ProtoMessage,
+    };
+
+    fn nested(s: &str) -> ComplexNested {
+        ComplexNested {
+            optional_string: Some(s.to_owned()),
+            ..ComplexNested::default()
+        }
+    }
+
+    // Map entries inserted in the given order.
+    fn complex(keys: &[i32]) -> Complex {
+        let mut m = Complex {
+            optional_enum: Some(ComplexEnum::Two),
+            repeated_bytes: vec![b"ab".to_vec()],
+            ..Complex::default()
+        };
+        for k in keys {
+            m.map_message.insert(*k, nested(&k.to_string()));
+        }
+        m
+    }
+
+    #[test]
+    fn is_stable() {
+        // Pinned, so it holds across processes, platforms and map backings.
+        assert_eq!(
+            complex(&[1, 2, 3]).fingerprint(),
+            0xa3d2fa86412b5eeeda6a032a9fb2b6b0
+        );
+        assert_eq!(
+            Complex::new().fingerprint(),
+            0x735473d0e4e290567c70f305e08f24bc
+        );
+    }
+
+    #[test]
+    fn ignores_map_order() {
+        let a = complex(&[1, 2, 3, 10, -4]);
+        let b = complex(&[-4, 10, 3, 2, 1]);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_eq!(
+            light::complex::Complex::from(a.clone()).fingerprint(),
+            a.fingerprint()
+        );
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn tells_contents_apart() {
+        let a = complex(&[1, 2]);
+        let mut b = complex(&[1, 2]);
+        b.map_message.insert(2, nested("x"));
+        assert_ne!(a.fingerprint(), b.fingerprint());
+
+        // The same values under different fields differ too.
+        let mut c = complex(&[1]);
+        c.repeated_bytes.clear();
+        let mut d = c.clone();
+        c.unknown_fields = vec![0x78, 0x01];
+        d.repeated_bytes.push(Vec::new());
+        assert_ne!(c.fingerprint(), d.fingerprint());
+    }
+}
@@ -1,34 +1,122 @@
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::hash_map::DefaultHasher,
     convert::{TryFrom, TryInto},
+    hash::{Hash, Hasher},
     vec,
 };
 
 use crate::{
+    codec,
+    descriptor::{
+        EnumDescriptor, EnumValueDescriptor, FieldDescriptor, Label, MessageDescriptor, Presence,
+        Type,
+    },
+    enumeration::ProtoEnum,
     error::AbsorbError,
-    value::{Enum, Key, Message, Rule, Value},
+    message::ProtoMessage,
+    reflect::ReflectBorrow,
+    value::{Enum, Key, Map, Message, Rule, Value},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Complex {
     pub optional_enum: Option<ComplexEnum>,
     pub repeated_bytes: Vec<Vec<u8>>,
-    pub map_message: HashMap<i32, ComplexNested>,
+    pub map_message: Map<i32, ComplexNested>,
+    pub unknown_fields: Vec<u8>,
 }
 
 impl Complex {
     pub fn new() -> Self {
         Self::default()
     }
+
+    pub fn clear_optional_enum(&mut self) {
+        self.optional_enum = None;
+    }
+
+    pub fn has_optional_enum(&self) -> bool {
+        self.optional_enum.is_some()
+    }
 }
 
-impl Default for Complex {
-    fn default() -> Self {
-        Complex {
-            optional_enum: None,
-            repeated_bytes: Vec::new(),
-            map_message: HashMap::new(),
-        }
+impl ProtoMessage for Complex {
+    fn descriptor() -> &'static MessageDescriptor {
+        static DESCRIPTOR: MessageDescriptor = MessageDescriptor {
+            name: "Complex",
+            fields: &[
+                FieldDescriptor {
+                    name: "optional_enum",
+                    number: 1,
+                    ty: Type::Enum,
+                    label: Label::Optional,
+                    presence: Presence::Explicit,
+                    key: None,
+                    message: None,
+                    enumeration: Some(ComplexEnum::descriptor),
+                    constraints: &[],
+                },
+                FieldDescriptor {
+                    name: "repeated_bytes",
+                    number: 2,
+                    ty: Type::Bytes,
+                    label: Label::Repeated,
+                    presence: Presence::Implicit,
+                    key: None,
+                    message: None,
+                    enumeration: None,
+                    constraints: &[],
+                },
+                FieldDescriptor {
+                    name: "map_message",
+                    number: 3,
+                    ty: Type::Message,
+                    label: Label::Repeated,
+                    presence: Presence::Implicit,
+                    key: Some(Type::Int32),
+                    message: Some(ComplexNested::descriptor),
+                    enumeration: None,
+                    constraints: &[],
+                },
+            ],
+        };
+        &DESCRIPTOR
+    }
+
+    fn as_message(&self) -> Cow<'_, Message> {
+        Cow::Owned(self.clone().into())
+    }
+}
+
+impl ReflectBorrow for Complex {}
+
+// Compares and hashes the fields in place, which agrees with eq::message_eq without converting
+// either side to a Message. Maps compare by key in any order.
+impl PartialEq for Complex {
+    fn eq(&self, other: &Self) -> bool {
+        self.optional_enum == other.optional_enum
+            && self.repeated_bytes == other.repeated_bytes
+            && self.map_message == other.map_message
+            && self.unknown_fields == other.unknown_fields
+    }
+}
+
+impl Eq for Complex {}
+
+// Map entries are hashed on their own and summed, so the hash does not depend on map order.
+impl Hash for Complex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.optional_enum.map(|v| v as i32).hash(state);
+        self.repeated_bytes.hash(state);
+        state.write_usize(self.map_message.len());
+        let entries = self.map_message.iter().fold(0u64, |sum, entry| {
+            let mut h = DefaultHasher::new();
+            entry.hash(&mut h);
+            sum.wrapping_add(h.finish())
+        });
+        state.write_u64(entries);
+        self.unknown_fields.hash(state);
     }
 }
 
@@ -46,6 +134,7 @@ impl From<Complex> for Message {
                         .collect(),
                 )))),
             ],
+            unknown_fields: m.unknown_fields,
         }
     }
 }
@@ -53,7 +142,9 @@ impl From<Complex> for Message {
 impl TryFrom<Message> for Complex {
     type Error = AbsorbError;
 
-    fn try_from(m: Message) -> Result<Self, Self::Error> {
+    fn try_from(mut m: Message) -> Result<Self, Self::Error> {
+        codec::retain_known_enums(&mut m, Self::descriptor());
+
         let mut fields = m.fields.into_iter();
         if fields.len() != 3 {
             return Err(AbsorbError::invalid_length(3, fields.len()));
@@ -64,30 +155,37 @@ impl TryFrom<Message> for Complex {
                 .next()
                 .unwrap()
                 .map(|v| match v {
-                    Value::Enum(Rule::Singular(v)) => ComplexEnum::new(v.number)
-                        .ok_or_else(|| AbsorbError::invalid_enum("ComplexEnum", &v)),
-                    v => Err(AbsorbError::invalid_type("optional_enum", &v)),
+                    Value::Enum(Rule::Singular(v)) => {
+                        ComplexEnum::try_from(v).map_err(|e| e.at("optional_enum"))
+                    }
+                    v => Err(AbsorbError::invalid_type(&Self::descriptor().fields[0], &v)),
                 })
                 .transpose()?,
             repeated_bytes: match fields.next().unwrap() {
                 Some(Value::Bytes(Rule::Repeated(v))) => Ok(v),
-                Some(v) => Err(AbsorbError::invalid_type("repeated_bytes", &v)),
+                Some(v) => Err(AbsorbError::invalid_type(&Self::descriptor().fields[1], &v)),
                 None => Err(AbsorbError::not_optional("repeated_bytes")),
             }?,
             map_message: match fields.next().unwrap() {
-                Some(Value::Message(Rule::Map(Key::I32(v)))) => {
-                    v.into_iter().map(|(k, v)| Ok((k, v.try_into()?))).collect()
-                }
-                Some(v) => Err(AbsorbError::invalid_type("map_message", &v)),
+                Some(Value::Message(Rule::Map(Key::I32(v)))) => v
+                    .into_iter()
+                    .map(|(k, v)| match v.try_into() {
+                        Ok(v) => Ok((k, v)),
+                        Err(e) => Err(AbsorbError::at_key(e, "map_message", k)),
+                    })
+                    .collect(),
+                Some(v) => Err(AbsorbError::invalid_type(&Self::descriptor().fields[2], &v)),
                 None => Err(AbsorbError::not_optional("map_message")),
             }?,
+            unknown_fields: m.unknown_fields,
         })
     }
 }
 
 #[repr(i32)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum ComplexEnum {
+    #[default]
     One = 1,
     Two = 2,
     Ten = 10,
@@ -104,34 +202,110 @@ impl ComplexEnum {
     }
 }
 
-impl Default for ComplexEnum {
-    fn default() -> Self {
-        ComplexEnum::One
-    }
-}
-
 impl From<ComplexEnum> for Enum {
     fn from(e: ComplexEnum) -> Self {
         Enum { number: e as i32 }
     }
 }
 
-#[derive(Debug, Clone)]
+impl TryFrom<Enum> for ComplexEnum {
+    type Error = AbsorbError;
+
+    fn try_from(e: Enum) -> Result<Self, Self::Error> {
+        ComplexEnum::new(e.number).ok_or_else(|| AbsorbError::invalid_enum("ComplexEnum", &e))
+    }
+}
+
+impl ProtoEnum for ComplexEnum {
+    fn descriptor() -> &'static EnumDescriptor {
+        static DESCRIPTOR: EnumDescriptor = EnumDescriptor {
+            name: "Complex.Enum",
+            closed: true,
+            values: &[
+                EnumValueDescriptor {
+                    name: "ONE",
+                    number: 1,
+                },
+                EnumValueDescriptor {
+                    name: "TWO",
+                    number: 2,
+                },
+                EnumValueDescriptor {
+                    name: "TEN",
+                    number: 10,
+                },
+            ],
+        };
+        &DESCRIPTOR
+    }
+
+    fn from_i32(number: i32) -> Option<Self> {
+        ComplexEnum::new(number)
+    }
+
+    fn values() -> &'static [Self] {
+        &[ComplexEnum::One, ComplexEnum::Two, ComplexEnum::Ten]
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct ComplexNested {
     pub optional_string: Option<String>,
+    pub unknown_fields: Vec<u8>,
 }
 
 impl ComplexNested {
     pub fn new() -> Self {
         Self::default()
     }
+
+    pub fn clear_optional_string(&mut self) {
+        self.optional_string = None;
+    }
+
+    pub fn has_optional_string(&self) -> bool {
+        self.optional_string.is_some()
+    }
 }
 
-impl Default for ComplexNested {
-    fn default() -> Self {
-        ComplexNested {
-            optional_string: None,
-        }
+impl ProtoMessage for ComplexNested {
+    fn descriptor() -> &'static MessageDescriptor {
+        static DESCRIPTOR: MessageDescriptor = MessageDescriptor {
+            name: "Complex.Nested",
+            fields: &[FieldDescriptor {
+                name: "optional_string",
+                number: 1,
+                ty: Type::String,
+                label: Label::Optional,
+                presence: Presence::Explicit,
+                key: None,
+                message: None,
+                enumeration: None,
+                constraints: &[],
+            }],
+        };
+        &DESCRIPTOR
+    }
+
+    fn as_message(&self) -> Cow<'_, Message> {
+        Cow::Owned(self.clone().into())
+    }
+}
+
+impl ReflectBorrow for ComplexNested {}
+
+impl PartialEq for ComplexNested {
+    fn eq(&self, other: &Self) -> bool {
+        self.optional_string == other.optional_string && self.unknown_fields == other.unknown_fields
+    }
+}
+
+impl Eq for ComplexNested {}
+
+impl Hash for ComplexNested {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.optional_string.hash(state);
+        self.unknown_fields.hash(state);
     }
 }
 
@@ -139,6 +313,7 @@ impl From<ComplexNested> for Message {
     fn from(m: ComplexNested) -> Self {
         Message {
             fields: vec![m.optional_string.map(|v| Value::String(Rule::Singular(v)))],
+            unknown_fields: m.unknown_fields,
         }
     }
 }
@@ -158,9 +333,10 @@ impl TryFrom<Message> for ComplexNested {
                 .unwrap()
                 .map(|v| match v {
                     Value::String(Rule::Singular(v)) => Ok(v),
-                    v => Err(AbsorbError::invalid_type("optional_string", &v)),
+                    v => Err(AbsorbError::invalid_type(&Self::descriptor().fields[0], &v)),
                 })
                 .transpose()?,
+            unknown_fields: m.unknown_fields,
         })
     }
 }
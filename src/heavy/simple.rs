@@ -1,13 +1,21 @@
-use std::convert::TryFrom;
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    hash::{Hash, Hasher},
+};
 
 use crate::{
+    descriptor::{FieldDescriptor, Label, MessageDescriptor, Presence, Type},
     error::AbsorbError,
+    message::ProtoMessage,
+    reflect::ReflectBorrow,
     value::{Message, Rule, Value},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Simple {
     pub simple_bool: bool,
+    pub unknown_fields: Vec<u8>,
 }
 
 impl Simple {
@@ -16,9 +24,46 @@ impl Simple {
     }
 }
 
-impl Default for Simple {
-    fn default() -> Self {
-        Simple { simple_bool: false }
+impl ProtoMessage for Simple {
+    fn descriptor() -> &'static MessageDescriptor {
+        static DESCRIPTOR: MessageDescriptor = MessageDescriptor {
+            name: "Simple",
+            fields: &[FieldDescriptor {
+                name: "simple_bool",
+                number: 1,
+                ty: Type::Bool,
+                label: Label::Optional,
+                presence: Presence::Implicit,
+                key: None,
+                message: None,
+                enumeration: None,
+                constraints: &[],
+            }],
+        };
+        &DESCRIPTOR
+    }
+
+    fn as_message(&self) -> Cow<'_, Message> {
+        Cow::Owned(self.clone().into())
+    }
+}
+
+impl ReflectBorrow for Simple {}
+
+// Compares and hashes the fields in place, which agrees with eq::message_eq without converting
+// either side to a Message.
+impl PartialEq for Simple {
+    fn eq(&self, other: &Self) -> bool {
+        self.simple_bool == other.simple_bool && self.unknown_fields == other.unknown_fields
+    }
+}
+
+impl Eq for Simple {}
+
+impl Hash for Simple {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.simple_bool.hash(state);
+        self.unknown_fields.hash(state);
     }
 }
 
@@ -26,6 +71,7 @@ impl From<Simple> for Message {
     fn from(m: Simple) -> Self {
         Message {
             fields: vec![Some(Value::Bool(Rule::Singular(m.simple_bool)))],
+            unknown_fields: m.unknown_fields,
         }
     }
 }
@@ -46,8 +92,9 @@ impl TryFrom<Message> for Simple {
                 .unwrap_or(Value::Bool(Rule::Singular(false)))
             {
                 Value::Bool(Rule::Singular(v)) => v,
-                v => return Err(AbsorbError::invalid_type("simple_bool", &v)),
+                v => return Err(AbsorbError::invalid_type(&Self::descriptor().fields[0], &v)),
             },
+            unknown_fields: m.unknown_fields,
         })
     }
 }
@@ -1,6 +1,58 @@
+pub mod codec;
+pub mod constraint;
+pub mod delimited;
 pub mod descriptor;
+pub mod diff;
+pub mod enumeration;
+pub mod eq;
 pub mod error;
+pub mod field_mask;
+pub mod fingerprint;
 pub mod heavy;
 pub mod light;
+pub mod list;
+pub mod map;
+pub mod message;
 pub mod reflect;
+pub mod testing;
+pub mod validate;
 pub mod value;
+pub mod wire;
+
+/// Derives `ProtoMessage` and the reflection traits for a hand-written struct. Each field names its
+/// protobuf field number and type, and the derive generates the conversions to and from
+/// `value::Message` along with a static `MessageDescriptor`.
+///
+/// `Option<T>` fields are optional with explicit presence, like proto3 `optional`, and `Vec<T>`
+/// fields are repeated, except `Vec<u8>` for `bytes`. Plain scalar fields have implicit presence
+/// and are not written to the wire when they hold their default. Map fields name their key type
+/// and can use any map type with key and value type arguments. `#[proto(required)]` marks a proto2
+/// required field, which `decode` and `absorb` insist on and `decode_partial` and `absorb_partial`
+/// do not. Open enum fields use `enumeration::EnumOrUnknown<E>` to keep numbers `E` does not
+/// define; numbers a closed enum does not define go to a `#[proto(unknown_fields)]` `Vec<u8>`.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use tobu_conversion::{
+///     heavy::complex::{ComplexEnum, ComplexNested},
+///     message::ProtoMessage,
+///     Reflect,
+/// };
+///
+/// #[derive(Debug, Clone, Default, Reflect)]
+/// pub struct Complex {
+///     #[proto(number = 1, ty = "enum")]
+///     pub optional_enum: Option<ComplexEnum>,
+///     #[proto(number = 2, ty = "bytes")]
+///     pub repeated_bytes: Vec<Vec<u8>>,
+///     #[proto(number = 3, ty = "message", key = "int32")]
+///     pub map_message: HashMap<i32, ComplexNested>,
+/// }
+///
+/// let mut m = Complex::default();
+/// m.optional_enum = Some(ComplexEnum::Ten);
+/// let mut buf = Vec::new();
+/// m.encode(&mut buf);
+/// assert_eq!(Complex::decode(&buf).unwrap().optional_enum, Some(ComplexEnum::Ten));
+/// ```
+pub use tobu_conversion_derive::Reflect;
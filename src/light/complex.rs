@@ -1,8 +1,23 @@
-use std::{collections::HashMap, convert::TryFrom};
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    hash::{Hash, Hasher},
+};
 
 use crate::{
+    codec,
+    descriptor::{
+        EnumDescriptor, EnumValueDescriptor, FieldDescriptor, Label, MessageDescriptor, Presence,
+        Type,
+    },
+    enumeration::ProtoEnum,
+    eq,
     error::AbsorbError,
-    value::{Enum, Key, Message, Rule, Value},
+    heavy,
+    message::ProtoMessage,
+    reflect::{ReflectBorrow, ReflectionMut},
+    validate,
+    value::{Enum, Key, Map, Message, Rule, Value},
 };
 
 #[repr(transparent)]
@@ -63,51 +78,25 @@ impl Complex {
         }
     }
 
-    pub fn map_message(&self) -> &HashMap<i32, ComplexNested> {
+    pub fn map_message(&self) -> &Map<i32, ComplexNested> {
         match &self.inner.fields[2] {
             Some(Value::Message(Rule::Map(Key::I32(v)))) => unsafe {
                 // Safety: ComplexNested is a repr(transparent) wrapper around a Message
-                &*(v as *const HashMap<i32, Message> as *const HashMap<i32, ComplexNested>)
+                &*(v as *const Map<i32, Message> as *const Map<i32, ComplexNested>)
             },
             _ => unreachable!(),
         }
     }
 
-    pub fn map_message_mut(&mut self) -> &mut HashMap<i32, ComplexNested> {
+    pub fn map_message_mut(&mut self) -> &mut Map<i32, ComplexNested> {
         match &mut self.inner.fields[2] {
             Some(Value::Message(Rule::Map(Key::I32(v)))) => unsafe {
                 // Safety: ComplexNested is a repr(transparent) wrapper around a Message
-                &mut *(v as *mut HashMap<i32, Message> as *mut HashMap<i32, ComplexNested>)
+                &mut *(v as *mut Map<i32, Message> as *mut Map<i32, ComplexNested>)
             },
             _ => unreachable!(),
         }
     }
-
-    fn validate(m: &Message) -> Option<AbsorbError> {
-        if m.fields.len() != 3 {
-            return Some(AbsorbError::invalid_length(3, m.fields.len()));
-        }
-
-        match &m.fields[0] {
-            Some(Value::Enum(Rule::Singular(v))) => ComplexEnum::validate(v),
-            Some(v) => Some(AbsorbError::invalid_type("optional_enum", v)),
-            None => None,
-        }?;
-
-        match &m.fields[1] {
-            Some(Value::Bytes(Rule::Repeated(_))) => None,
-            Some(v) => Some(AbsorbError::invalid_type("repeated_bytes", v)),
-            None => Some(AbsorbError::not_optional("repeated_bytes")),
-        }?;
-
-        match &m.fields[2] {
-            Some(Value::Message(Rule::Map(Key::I32(v)))) => {
-                v.values().find_map(ComplexNested::validate)
-            }
-            Some(v) => Some(AbsorbError::invalid_type("map_message", v)),
-            None => Some(AbsorbError::not_optional("map_message")),
-        }
-    }
 }
 
 impl Default for Complex {
@@ -117,13 +106,83 @@ impl Default for Complex {
                 fields: vec![
                     None,
                     Some(Value::Bytes(Rule::Repeated(Vec::new()))),
-                    Some(Value::Message(Rule::Map(Key::I32(HashMap::new())))),
+                    Some(Value::Message(Rule::Map(Key::I32(Map::new())))),
                 ],
+                unknown_fields: Vec::new(),
             },
         }
     }
 }
 
+impl ProtoMessage for Complex {
+    fn descriptor() -> &'static MessageDescriptor {
+        static DESCRIPTOR: MessageDescriptor = MessageDescriptor {
+            name: "Complex",
+            fields: &[
+                FieldDescriptor {
+                    name: "optional_enum",
+                    number: 1,
+                    ty: Type::Enum,
+                    label: Label::Optional,
+                    presence: Presence::Explicit,
+                    key: None,
+                    message: None,
+                    enumeration: Some(ComplexEnum::descriptor),
+                    constraints: &[],
+                },
+                FieldDescriptor {
+                    name: "repeated_bytes",
+                    number: 2,
+                    ty: Type::Bytes,
+                    label: Label::Repeated,
+                    presence: Presence::Implicit,
+                    key: None,
+                    message: None,
+                    enumeration: None,
+                    constraints: &[],
+                },
+                FieldDescriptor {
+                    name: "map_message",
+                    number: 3,
+                    ty: Type::Message,
+                    label: Label::Repeated,
+                    presence: Presence::Implicit,
+                    key: Some(Type::Int32),
+                    message: Some(ComplexNested::descriptor),
+                    enumeration: None,
+                    constraints: &[],
+                },
+            ],
+        };
+        &DESCRIPTOR
+    }
+
+    fn as_message(&self) -> Cow<'_, Message> {
+        Cow::Borrowed(&self.inner)
+    }
+}
+
+impl ReflectBorrow for Complex {
+    fn reflect_mut(&mut self) -> ReflectionMut<'_, Self> {
+        ReflectionMut::borrowed(&mut self.inner)
+    }
+}
+
+// Unset fields without presence equal their defaults, which a derived PartialEq would miss.
+impl PartialEq for Complex {
+    fn eq(&self, other: &Self) -> bool {
+        eq::message_eq(self, other)
+    }
+}
+
+impl Eq for Complex {}
+
+impl Hash for Complex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u128(self.fingerprint());
+    }
+}
+
 impl From<Complex> for Message {
     fn from(m: Complex) -> Self {
         m.inner
@@ -133,18 +192,18 @@ impl From<Complex> for Message {
 impl TryFrom<Message> for Complex {
     type Error = AbsorbError;
 
-    fn try_from(m: Message) -> Result<Self, Self::Error> {
-        if let Some(err) = Self::validate(&m) {
-            return Err(err);
-        }
+    fn try_from(mut m: Message) -> Result<Self, Self::Error> {
+        codec::retain_known_enums(&mut m, Self::descriptor());
+        validate::validate(&m, Self::descriptor())?;
 
         Ok(Complex { inner: m })
     }
 }
 
 #[repr(i32)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub enum ComplexEnum {
+    #[default]
     One = 1,
     Two = 2,
     Ten = 10,
@@ -159,13 +218,6 @@ impl ComplexEnum {
             _ => None,
         }
     }
-
-    fn validate(e: &Enum) -> Option<AbsorbError> {
-        match e.number {
-            1 | 2 | 10 => None,
-            _ => Some(AbsorbError::invalid_enum("ComplexEnum", e)),
-        }
-    }
 }
 
 impl From<ComplexEnum> for Enum {
@@ -174,9 +226,43 @@ impl From<ComplexEnum> for Enum {
     }
 }
 
-impl Default for ComplexEnum {
-    fn default() -> Self {
-        ComplexEnum::One
+impl TryFrom<Enum> for ComplexEnum {
+    type Error = AbsorbError;
+
+    fn try_from(e: Enum) -> Result<Self, Self::Error> {
+        ComplexEnum::new(e.number).ok_or_else(|| AbsorbError::invalid_enum("ComplexEnum", &e))
+    }
+}
+
+impl ProtoEnum for ComplexEnum {
+    fn descriptor() -> &'static EnumDescriptor {
+        static DESCRIPTOR: EnumDescriptor = EnumDescriptor {
+            name: "Complex.Enum",
+            closed: true,
+            values: &[
+                EnumValueDescriptor {
+                    name: "ONE",
+                    number: 1,
+                },
+                EnumValueDescriptor {
+                    name: "TWO",
+                    number: 2,
+                },
+                EnumValueDescriptor {
+                    name: "TEN",
+                    number: 10,
+                },
+            ],
+        };
+        &DESCRIPTOR
+    }
+
+    fn from_i32(number: i32) -> Option<Self> {
+        ComplexEnum::new(number)
+    }
+
+    fn values() -> &'static [Self] {
+        &[ComplexEnum::One, ComplexEnum::Two, ComplexEnum::Ten]
     }
 }
 
@@ -205,37 +291,70 @@ impl ComplexNested {
         }
     }
 
-    pub fn clear_optional_enum(&mut self) {
-        if let Some(v) = &mut self.inner.fields[0] {
-            *v = Value::String(Rule::Singular("".to_string()))
-        }
+    pub fn clear_optional_string(&mut self) {
+        self.inner.fields[0] = None;
     }
 
-    pub fn has_optional_enum(&self) -> bool {
+    pub fn has_optional_string(&self) -> bool {
         self.inner.fields[0].is_some()
     }
-
-    fn validate(m: &Message) -> Option<AbsorbError> {
-        if m.fields.len() != 1 {
-            return Some(AbsorbError::invalid_length(1, m.fields.len()));
-        }
-
-        match &m.fields[0] {
-            Some(Value::String(Rule::Singular(_))) => None,
-            Some(v) => Some(AbsorbError::invalid_type("optional_string", v)),
-            None => None,
-        }
-    }
 }
 
 impl Default for ComplexNested {
     fn default() -> Self {
         ComplexNested {
-            inner: Message { fields: vec![None] },
+            inner: Message {
+                fields: vec![None],
+                unknown_fields: Vec::new(),
+            },
         }
     }
 }
 
+impl ProtoMessage for ComplexNested {
+    fn descriptor() -> &'static MessageDescriptor {
+        static DESCRIPTOR: MessageDescriptor = MessageDescriptor {
+            name: "Complex.Nested",
+            fields: &[FieldDescriptor {
+                name: "optional_string",
+                number: 1,
+                ty: Type::String,
+                label: Label::Optional,
+                presence: Presence::Explicit,
+                key: None,
+                message: None,
+                enumeration: None,
+                constraints: &[],
+            }],
+        };
+        &DESCRIPTOR
+    }
+
+    fn as_message(&self) -> Cow<'_, Message> {
+        Cow::Borrowed(&self.inner)
+    }
+}
+
+impl ReflectBorrow for ComplexNested {
+    fn reflect_mut(&mut self) -> ReflectionMut<'_, Self> {
+        ReflectionMut::borrowed(&mut self.inner)
+    }
+}
+
+impl PartialEq for ComplexNested {
+    fn eq(&self, other: &Self) -> bool {
+        eq::message_eq(self, other)
+    }
+}
+
+impl Eq for ComplexNested {}
+
+impl Hash for ComplexNested {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u128(self.fingerprint());
+    }
+}
+
 impl From<ComplexNested> for Message {
     fn from(m: ComplexNested) -> Self {
         m.inner
@@ -246,47 +365,99 @@ impl TryFrom<Message> for ComplexNested {
     type Error = AbsorbError;
 
     fn try_from(m: Message) -> Result<Self, Self::Error> {
-        if let Some(err) = Self::validate(&m) {
-            return Err(err);
-        }
+        validate::validate(&m, Self::descriptor())?;
 
         Ok(ComplexNested { inner: m })
     }
 }
 
-//#[cfg(test)]
-//mod tests {
-//    use super::*;
-//
-//    #[test]
-//    fn map_works() {
-//        let mut c = Complex::new();
-//
-//        let mut cn = ComplexNested::new();
-//        *cn.optional_string_mut() = "hello".to_string();
-//        c.map_message_mut().insert(1, cn);
-//
-//        let mut cn = ComplexNested::new();
-//        *cn.optional_string_mut() = "world".to_string();
-//        c.map_message_mut().insert(2, cn);
-//
-//        let m = match &c.inner.fields[2] {
-//            Some(Value::Message(Rule::Map(Key::I32(v)))) => v,
-//            _ => unreachable!(),
-//        };
-//
-//        let v = match &m[&1].fields[0] {
-//            Some(Value::String(Rule::Singular(v))) => v,
-//            _ => unreachable!(),
-//        };
-//
-//        assert_eq!(v, "hello");
-//
-//        let v = match &m[&2].fields[0] {
-//            Some(Value::String(Rule::Singular(v))) => v,
-//            _ => unreachable!(),
-//        };
-//
-//        assert_eq!(v, "world");
-//    }
-//}
+// The heavy types are always well typed, so their fields can be moved in without validation.
+impl From<heavy::complex::Complex> for Complex {
+    fn from(m: heavy::complex::Complex) -> Self {
+        Complex { inner: m.into() }
+    }
+}
+
+impl From<Complex> for heavy::complex::Complex {
+    fn from(m: Complex) -> Self {
+        let mut fields = m.inner.fields.into_iter();
+        heavy::complex::Complex {
+            optional_enum: match fields.next().unwrap() {
+                Some(Value::Enum(Rule::Singular(v))) => {
+                    heavy::complex::ComplexEnum::new(v.number).or_else(|| unreachable!())
+                }
+                Some(_) => unreachable!(),
+                None => None,
+            },
+            repeated_bytes: match fields.next().unwrap() {
+                Some(Value::Bytes(Rule::Repeated(v))) => v,
+                _ => unreachable!(),
+            },
+            map_message: match fields.next().unwrap() {
+                Some(Value::Message(Rule::Map(Key::I32(v)))) => v
+                    .into_iter()
+                    .map(|(k, v)| (k, ComplexNested { inner: v }.into()))
+                    .collect(),
+                _ => unreachable!(),
+            },
+            unknown_fields: m.inner.unknown_fields,
+        }
+    }
+}
+
+impl From<heavy::complex::ComplexNested> for ComplexNested {
+    fn from(m: heavy::complex::ComplexNested) -> Self {
+        ComplexNested { inner: m.into() }
+    }
+}
+
+impl From<ComplexNested> for heavy::complex::ComplexNested {
+    fn from(m: ComplexNested) -> Self {
+        let mut fields = m.inner.fields.into_iter();
+        heavy::complex::ComplexNested {
+            optional_string: match fields.next().unwrap() {
+                Some(Value::String(Rule::Singular(v))) => Some(v),
+                Some(_) => unreachable!(),
+                None => None,
+            },
+            unknown_fields: m.inner.unknown_fields,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_works() {
+        let mut c = Complex::new();
+
+        let mut cn = ComplexNested::new();
+        *cn.optional_string_mut() = "hello".to_string();
+        c.map_message_mut().insert(1, cn);
+
+        let mut cn = ComplexNested::new();
+        *cn.optional_string_mut() = "world".to_string();
+        c.map_message_mut().insert(2, cn);
+
+        let m = match &c.inner.fields[2] {
+            Some(Value::Message(Rule::Map(Key::I32(v)))) => v,
+            _ => unreachable!(),
+        };
+
+        let v = match &m[&1].fields[0] {
+            Some(Value::String(Rule::Singular(v))) => v,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(v, "hello");
+
+        let v = match &m[&2].fields[0] {
+            Some(Value::String(Rule::Singular(v))) => v,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(v, "world");
+    }
+}
@@ -1,7 +1,17 @@
-use std::convert::TryFrom;
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    hash::{Hash, Hasher},
+};
 
 use crate::{
+    descriptor::{FieldDescriptor, Label, MessageDescriptor, Presence, Type},
+    eq,
     error::AbsorbError,
+    heavy,
+    message::ProtoMessage,
+    reflect::{ReflectBorrow, ReflectionMut},
+    validate,
     value::{Message, Rule, Value},
 };
 
@@ -19,28 +29,17 @@ impl Simple {
     pub fn simple_bool(&self) -> bool {
         match &self.inner.fields[0] {
             Some(Value::Bool(Rule::Singular(v))) => *v,
-            _ => unreachable!(),
+            Some(_) => unreachable!(),
+            None => false,
         }
     }
 
     pub fn simple_bool_mut(&mut self) -> &mut bool {
-        match &mut self.inner.fields[0] {
-            Some(Value::Bool(Rule::Singular(v))) => v,
+        match self.inner.fields[0].get_or_insert(Value::Bool(Rule::Singular(false))) {
+            Value::Bool(Rule::Singular(v)) => v,
             _ => unreachable!(),
         }
     }
-
-    fn validate(m: &Message) -> Option<AbsorbError> {
-        if m.fields.len() != 1 {
-            return Some(AbsorbError::invalid_length(1, m.fields.len()));
-        }
-
-        match &m.fields[0] {
-            Some(Value::Bool(Rule::Singular(_))) => None,
-            Some(v) => Some(AbsorbError::invalid_type("simple_bool", v)),
-            None => Some(AbsorbError::not_optional("simple_bool")),
-        }
-    }
 }
 
 impl Default for Simple {
@@ -48,11 +47,57 @@ impl Default for Simple {
         Simple {
             inner: Message {
                 fields: vec![Some(Value::Bool(Rule::Singular(false)))],
+                unknown_fields: Vec::new(),
             },
         }
     }
 }
 
+impl ProtoMessage for Simple {
+    fn descriptor() -> &'static MessageDescriptor {
+        static DESCRIPTOR: MessageDescriptor = MessageDescriptor {
+            name: "Simple",
+            fields: &[FieldDescriptor {
+                name: "simple_bool",
+                number: 1,
+                ty: Type::Bool,
+                label: Label::Optional,
+                presence: Presence::Implicit,
+                key: None,
+                message: None,
+                enumeration: None,
+                constraints: &[],
+            }],
+        };
+        &DESCRIPTOR
+    }
+
+    fn as_message(&self) -> Cow<'_, Message> {
+        Cow::Borrowed(&self.inner)
+    }
+}
+
+impl ReflectBorrow for Simple {
+    fn reflect_mut(&mut self) -> ReflectionMut<'_, Self> {
+        ReflectionMut::borrowed(&mut self.inner)
+    }
+}
+
+// Unset fields without presence equal their defaults, which a derived PartialEq would miss.
+impl PartialEq for Simple {
+    fn eq(&self, other: &Self) -> bool {
+        eq::message_eq(self, other)
+    }
+}
+
+impl Eq for Simple {}
+
+impl Hash for Simple {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u128(self.fingerprint());
+    }
+}
+
 impl From<Simple> for Message {
     fn from(m: Simple) -> Self {
         m.inner
@@ -63,10 +108,29 @@ impl TryFrom<Message> for Simple {
     type Error = AbsorbError;
 
     fn try_from(m: Message) -> Result<Self, Self::Error> {
-        if let Some(err) = Self::validate(&m) {
-            return Err(err);
-        }
+        validate::validate(&m, Self::descriptor())?;
 
         Ok(Simple { inner: m })
     }
 }
+
+// The heavy type is always well typed, so its fields can be moved in without validation.
+impl From<heavy::simple::Simple> for Simple {
+    fn from(m: heavy::simple::Simple) -> Self {
+        Simple { inner: m.into() }
+    }
+}
+
+impl From<Simple> for heavy::simple::Simple {
+    fn from(m: Simple) -> Self {
+        let mut fields = m.inner.fields.into_iter();
+        heavy::simple::Simple {
+            simple_bool: match fields.next().unwrap() {
+                Some(Value::Bool(Rule::Singular(v))) => v,
+                Some(_) => unreachable!(),
+                None => false,
+            },
+            unknown_fields: m.inner.unknown_fields,
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{
+    descriptor::FieldDescriptor,
+    error::AbsorbError,
+    validate,
+    value::{Rule, Value, ValueRef, Variant},
+};
+
+// Type-erased access to a repeated field, like protoreflect's List. Elements are passed in as
+// singular values and handed out as ValueRef.
+pub struct ReflectList<'a> {
+    field: &'static FieldDescriptor,
+    list: &'a mut dyn List,
+}
+
+impl<'a> ReflectList<'a> {
+    // Returns None if the value is not a list of the field's type.
+    pub fn new(field: &'static FieldDescriptor, value: &'a mut Value) -> Option<Self> {
+        if !field.matches(value) {
+            return None;
+        }
+        let list: &mut dyn List = match value {
+            Value::Bool(Rule::Repeated(v)) => v,
+            Value::Bytes(Rule::Repeated(v)) => v,
+            Value::Enum(Rule::Repeated(v)) => v,
+            Value::F32(Rule::Repeated(v)) => v,
+            Value::F64(Rule::Repeated(v)) => v,
+            Value::I32(Rule::Repeated(v)) => v,
+            Value::I64(Rule::Repeated(v)) => v,
+            Value::Message(Rule::Repeated(v)) => v,
+            Value::String(Rule::Repeated(v)) => v,
+            Value::U32(Rule::Repeated(v)) => v,
+            Value::U64(Rule::Repeated(v)) => v,
+            _ => return None,
+        };
+        Some(ReflectList { field, list })
+    }
+
+    pub fn field(&self) -> &'static FieldDescriptor {
+        self.field
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<ValueRef<'_>> {
+        self.list.get(i)
+    }
+
+    pub fn set(&mut self, i: usize, value: Value) -> Result<(), AbsorbError> {
+        if i >= self.len() {
+            return Err(AbsorbError::out_of_bounds(self.field, i, self.len()));
+        }
+        validate::check_element(&value, self.field)?;
+        self.list.set(i, value);
+        Ok(())
+    }
+
+    pub fn append(&mut self, value: Value) -> Result<(), AbsorbError> {
+        validate::check_element(&value, self.field)?;
+        self.list.push(value);
+        Ok(())
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.list.truncate(len)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ValueRef<'_>> {
+        self.list.iter()
+    }
+}
+
+trait List {
+    fn len(&self) -> usize;
+    fn get(&self, i: usize) -> Option<ValueRef<'_>>;
+    fn set(&mut self, i: usize, value: Value);
+    fn push(&mut self, value: Value);
+    fn truncate(&mut self, len: usize);
+    fn iter(&self) -> Box<dyn Iterator<Item = ValueRef<'_>> + '_>;
+}
+
+impl<T: Variant> List for Vec<T> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn get(&self, i: usize) -> Option<ValueRef<'_>> {
+        self.as_slice().get(i).map(T::as_ref)
+    }
+
+    fn set(&mut self, i: usize, value: Value) {
+        self[i] = T::from_element(value);
+    }
+
+    fn push(&mut self, value: Value) {
+        Vec::push(self, T::from_element(value));
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = ValueRef<'_>> + '_> {
+        Box::new(self.as_slice().iter().map(T::as_ref))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::ErrorKind, heavy::complex::Complex, message::ProtoMessage};
+
+    #[test]
+    fn set_checks_bounds_and_type() {
+        let field = &Complex::descriptor().fields[1];
+        let mut value = Value::Bytes(Rule::Repeated(vec![b"a".to_vec()]));
+        let mut list = ReflectList::new(field, &mut value).unwrap();
+
+        list.set(0, Value::Bytes(Rule::Singular(b"b".to_vec())))
+            .unwrap();
+        assert_eq!(list.get(0), Some(ValueRef::Bytes(b"b")));
+
+        let e = list
+            .set(1, Value::Bytes(Rule::Singular(b"c".to_vec())))
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::OutOfBounds);
+        let e = list.set(0, Value::Bool(Rule::Singular(true))).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidType);
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn new_rejects_values_of_another_field() {
+        let field = &Complex::descriptor().fields[1];
+        let mut value = Value::I32(Rule::Repeated(vec![1]));
+        assert!(ReflectList::new(field, &mut value).is_none());
+        let mut value = Value::Bytes(Rule::Singular(b"a".to_vec()));
+        assert!(ReflectList::new(field, &mut value).is_none());
+    }
+}
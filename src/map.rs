@@ -0,0 +1,218 @@
+use crate::{
+    descriptor::FieldDescriptor,
+    error::AbsorbError,
+    validate,
+    value::{self, Key, KeyVariant, Rule, Value, ValueRef, Variant},
+};
+
+// Type-erased access to a map field, like protoreflect's Map. Keys are passed as ValueRef and
+// values as singular values.
+pub struct ReflectMap<'a> {
+    field: &'static FieldDescriptor,
+    map: &'a mut dyn Map,
+}
+
+impl<'a> ReflectMap<'a> {
+    // Returns None if the value is not a map of the field's key and value types.
+    pub fn new(field: &'static FieldDescriptor, value: &'a mut Value) -> Option<Self> {
+        if !field.matches(value) {
+            return None;
+        }
+        let map = match value {
+            Value::Bool(Rule::Map(k)) => erase(k),
+            Value::Bytes(Rule::Map(k)) => erase(k),
+            Value::Enum(Rule::Map(k)) => erase(k),
+            Value::F32(Rule::Map(k)) => erase(k),
+            Value::F64(Rule::Map(k)) => erase(k),
+            Value::I32(Rule::Map(k)) => erase(k),
+            Value::I64(Rule::Map(k)) => erase(k),
+            Value::Message(Rule::Map(k)) => erase(k),
+            Value::String(Rule::Map(k)) => erase(k),
+            Value::U32(Rule::Map(k)) => erase(k),
+            Value::U64(Rule::Map(k)) => erase(k),
+            _ => return None,
+        };
+        Some(ReflectMap { field, map })
+    }
+
+    pub fn field(&self) -> &'static FieldDescriptor {
+        self.field
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn has(&self, key: ValueRef) -> bool {
+        self.map.get(key).is_some()
+    }
+
+    pub fn get(&self, key: ValueRef) -> Option<ValueRef<'_>> {
+        self.map.get(key)
+    }
+
+    pub fn set(&mut self, key: ValueRef, value: Value) -> Result<(), AbsorbError> {
+        validate::check_element(&value, self.field)?;
+        match self.map.insert(key, value) {
+            true => Ok(()),
+            false => Err(AbsorbError::invalid_key(self.field, &key.to_value())),
+        }
+    }
+
+    // Inserts the default value for the field if the key is not present.
+    pub fn get_or_insert(&mut self, key: ValueRef) -> Result<ValueMut<'_>, AbsorbError> {
+        let default = Value::default_for(self.field)
+            .ok_or_else(|| AbsorbError::missing_descriptor(self.field.name))?;
+        let field = self.field;
+        match self.map.get_or_insert(key, default) {
+            Some(element) => Ok(ValueMut { field, element }),
+            None => Err(AbsorbError::invalid_key(field, &key.to_value())),
+        }
+    }
+
+    pub fn remove(&mut self, key: ValueRef) -> Option<Value> {
+        self.map.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ValueRef<'_>, ValueRef<'_>)> {
+        self.map.iter()
+    }
+}
+
+// A value held by a map, which can be replaced in place. Writes are checked like set.
+pub struct ValueMut<'a> {
+    field: &'static FieldDescriptor,
+    element: &'a mut dyn Element,
+}
+
+impl<'a> ValueMut<'a> {
+    pub fn get(&self) -> ValueRef<'_> {
+        self.element.get()
+    }
+
+    pub fn set(&mut self, value: Value) -> Result<(), AbsorbError> {
+        validate::check_element(&value, self.field)?;
+        self.element.replace(value);
+        Ok(())
+    }
+}
+
+trait Element {
+    fn get(&self) -> ValueRef<'_>;
+    fn replace(&mut self, value: Value);
+}
+
+impl<T: Variant> Element for T {
+    fn get(&self) -> ValueRef<'_> {
+        self.as_ref()
+    }
+
+    fn replace(&mut self, value: Value) {
+        *self = T::from_element(value);
+    }
+}
+
+fn erase<T: Variant>(key: &mut Key<T>) -> &mut dyn Map {
+    match key {
+        Key::Bool(m) => m,
+        Key::I32(m) => m,
+        Key::I64(m) => m,
+        Key::String(m) => m,
+        Key::U32(m) => m,
+        Key::U64(m) => m,
+    }
+}
+
+// Operations that take a key return None or false when it has the wrong type.
+trait Map {
+    fn len(&self) -> usize;
+    fn get(&self, key: ValueRef) -> Option<ValueRef<'_>>;
+    fn insert(&mut self, key: ValueRef, value: Value) -> bool;
+    fn get_or_insert(&mut self, key: ValueRef, default: Value) -> Option<&mut dyn Element>;
+    fn remove(&mut self, key: ValueRef) -> Option<Value>;
+    fn iter(&self) -> Box<dyn Iterator<Item = (ValueRef<'_>, ValueRef<'_>)> + '_>;
+}
+
+impl<K: KeyVariant, T: Variant> Map for value::Map<K, T> {
+    fn len(&self) -> usize {
+        value::Map::len(self)
+    }
+
+    fn get(&self, key: ValueRef) -> Option<ValueRef<'_>> {
+        value::Map::get(self, &K::from_ref(key)?).map(T::as_ref)
+    }
+
+    fn insert(&mut self, key: ValueRef, value: Value) -> bool {
+        match K::from_ref(key) {
+            Some(k) => {
+                value::Map::insert(self, k, T::from_element(value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn get_or_insert(&mut self, key: ValueRef, default: Value) -> Option<&mut dyn Element> {
+        let v = self
+            .entry(K::from_ref(key)?)
+            .or_insert_with(|| T::from_element(default));
+        Some(v)
+    }
+
+    fn remove(&mut self, key: ValueRef) -> Option<Value> {
+        let v = value::remove_entry(self, &K::from_ref(key)?)?;
+        Some(T::into_value(Rule::Singular(v)))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (ValueRef<'_>, ValueRef<'_>)> + '_> {
+        Box::new(value::Map::iter(self).map(|(k, v)| (k.as_ref(), v.as_ref())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::ErrorKind,
+        heavy::complex::{Complex, ComplexNested},
+        message::ProtoMessage,
+        value::Message,
+    };
+
+    #[test]
+    fn get_or_insert_hands_out_a_mutable_value() {
+        let field = &Complex::descriptor().fields[2];
+        let mut value = Value::Message(Rule::Map(Key::I32(value::Map::new())));
+        let mut map = ReflectMap::new(field, &mut value).unwrap();
+
+        let empty = Message::new(ComplexNested::descriptor());
+        let mut v = map.get_or_insert(ValueRef::I32(1)).unwrap();
+        assert_eq!(v.get(), ValueRef::Message(&empty));
+
+        let nested = Message::from(ComplexNested {
+            optional_string: Some("x".to_owned()),
+            ..ComplexNested::default()
+        });
+        v.set(Value::Message(Rule::Singular(nested.clone())))
+            .unwrap();
+        let e = v.set(Value::Bool(Rule::Singular(true))).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidType);
+        assert_eq!(map.get(ValueRef::I32(1)), Some(ValueRef::Message(&nested)));
+
+        let e = map.get_or_insert(ValueRef::Bool(true)).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidType);
+    }
+
+    #[test]
+    fn new_rejects_values_of_another_field() {
+        let field = &Complex::descriptor().fields[2];
+        let mut value = Value::Bytes(Rule::Map(Key::I32(value::Map::new())));
+        assert!(ReflectMap::new(field, &mut value).is_none());
+        let mut value = Value::Message(Rule::Map(Key::String(value::Map::new())));
+        assert!(ReflectMap::new(field, &mut value).is_none());
+    }
+}
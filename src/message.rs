@@ -0,0 +1,360 @@
+use std::{borrow::Cow, convert::TryFrom, fmt::Display};
+
+use crate::{
+    codec::{self, EncodeOptions},
+    constraint,
+    descriptor::{MessageDescriptor, Presence},
+    error::{AbsorbError, ConstraintError, DecodeError},
+    fingerprint,
+    value::{Key, Map, Message, Rule, Value},
+};
+
+// Holds for every ProtoMessage: converting a value yields a message that matches its descriptor.
+const VALID: &str = "invariant: a ProtoMessage converts to a message matching its descriptor";
+
+pub trait ProtoMessage:
+    Clone + Default + Into<Message> + TryFrom<Message, Error = AbsorbError>
+{
+    fn descriptor() -> &'static MessageDescriptor;
+
+    // The message behind this value. Types backed by a Message borrow it, others convert a copy.
+    // The default methods below all read through it.
+    fn as_message(&self) -> Cow<'_, Message>;
+
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn merge_from(&mut self, other: &Self) {
+        let mut m = self.as_message().into_owned();
+        m.merge_from(&other.as_message(), Self::descriptor())
+            .expect(VALID);
+        *self = Self::try_from(m).expect("invariant: merging two valid messages is valid");
+    }
+
+    fn is_initialized(&self) -> bool {
+        is_initialized(&self.as_message(), Self::descriptor())
+    }
+
+    fn missing_required_fields(&self) -> Vec<String> {
+        missing_required_fields(&self.as_message(), Self::descriptor())
+    }
+
+    fn check_constraints(&self) -> Result<(), ConstraintError> {
+        constraint::check(&self.as_message(), Self::descriptor())
+    }
+
+    fn fingerprint(&self) -> u128 {
+        fingerprint::fingerprint(&self.as_message(), Self::descriptor())
+    }
+
+    fn encoded_len(&self) -> usize {
+        codec::encoded_len(&self.as_message(), Self::descriptor()).expect(VALID)
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        codec::encode(&self.as_message(), Self::descriptor(), buf).expect(VALID)
+    }
+
+    fn encode_with(&self, options: &EncodeOptions, buf: &mut Vec<u8>) {
+        codec::encode_with(&self.as_message(), Self::descriptor(), options, buf).expect(VALID)
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut m: Message = Self::default().into();
+        codec::merge(&mut m, Self::descriptor(), buf)?;
+        check_initialized(&m, Self::descriptor())?;
+        Ok(Self::try_from(m)?)
+    }
+
+    // Like decode, but leaves required fields unchecked.
+    fn decode_partial(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut m: Message = Self::default().into();
+        codec::merge(&mut m, Self::descriptor(), buf)?;
+        Ok(Self::try_from(m)?)
+    }
+}
+
+pub fn is_initialized(m: &Message, d: &MessageDescriptor) -> bool {
+    m.fields.iter().zip(d.fields).all(|(v, f)| match v {
+        None => f.presence != Presence::LegacyRequired,
+        Some(Value::Message(rule)) => match f.message_descriptor() {
+            Some(d) => match rule {
+                Rule::Singular(m) => is_initialized(m, d),
+                Rule::Repeated(v) => v.iter().all(|m| is_initialized(m, d)),
+                Rule::Map(k) => match k {
+                    Key::Bool(v) => v.values().all(|m| is_initialized(m, d)),
+                    Key::I32(v) => v.values().all(|m| is_initialized(m, d)),
+                    Key::I64(v) => v.values().all(|m| is_initialized(m, d)),
+                    Key::String(v) => v.values().all(|m| is_initialized(m, d)),
+                    Key::U32(v) => v.values().all(|m| is_initialized(m, d)),
+                    Key::U64(v) => v.values().all(|m| is_initialized(m, d)),
+                },
+            },
+            None => true,
+        },
+        Some(_) => true,
+    })
+}
+
+pub fn check_initialized(m: &Message, d: &MessageDescriptor) -> Result<(), AbsorbError> {
+    if is_initialized(m, d) {
+        return Ok(());
+    }
+    Err(AbsorbError::missing_required(missing_required_fields(m, d)))
+}
+
+// Paths of unset required fields, e.g. `map_message[1].name` or `nested.list[0].name`.
+pub fn missing_required_fields(m: &Message, d: &MessageDescriptor) -> Vec<String> {
+    let mut missing = Vec::new();
+    collect_missing(m, d, "", &mut missing);
+    missing
+}
+
+fn collect_missing(m: &Message, d: &MessageDescriptor, prefix: &str, missing: &mut Vec<String>) {
+    for (v, f) in m.fields.iter().zip(d.fields) {
+        let path = format!("{}{}", prefix, f.name);
+        let (rule, d) = match (v, f.message_descriptor()) {
+            (None, _) if f.presence == Presence::LegacyRequired => {
+                missing.push(path);
+                continue;
+            }
+            (Some(Value::Message(rule)), Some(d)) => (rule, d),
+            _ => continue,
+        };
+
+        match rule {
+            Rule::Singular(m) => collect_missing(m, d, &format!("{}.", path), missing),
+            Rule::Repeated(v) => v
+                .iter()
+                .enumerate()
+                .for_each(|(i, m)| collect_missing(m, d, &format!("{}[{}].", path, i), missing)),
+            Rule::Map(k) => match k {
+                Key::Bool(v) => collect_missing_entries(v, d, &path, missing),
+                Key::I32(v) => collect_missing_entries(v, d, &path, missing),
+                Key::I64(v) => collect_missing_entries(v, d, &path, missing),
+                Key::String(v) => collect_missing_entries(v, d, &path, missing),
+                Key::U32(v) => collect_missing_entries(v, d, &path, missing),
+                Key::U64(v) => collect_missing_entries(v, d, &path, missing),
+            },
+        }
+    }
+}
+
+fn collect_missing_entries<K: Ord + Display>(
+    m: &Map<K, Message>,
+    d: &MessageDescriptor,
+    path: &str,
+    missing: &mut Vec<String>,
+) {
+    // Visit entries in key order so the reported paths are stable.
+    let mut entries: Vec<_> = m.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    for (k, m) in entries {
+        collect_missing(m, d, &format!("{}[{}].", path, k), missing);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
+    use super::*;
+    use crate::{heavy, light};
+
+    fn heavy_complex() -> heavy::complex::Complex {
+        let mut nested = heavy::complex::ComplexNested::new();
+        nested.optional_string = Some("x".to_owned());
+
+        let mut m = heavy::complex::Complex::new();
+        m.optional_enum = Some(heavy::complex::ComplexEnum::Ten);
+        m.repeated_bytes = vec![b"ab".to_vec()];
+        m.map_message.insert(1, nested);
+        m
+    }
+
+    fn encoded<T: ProtoMessage>(m: &T) -> Vec<u8> {
+        let mut buf = Vec::new();
+        m.encode(&mut buf);
+        assert_eq!(buf.len(), m.encoded_len());
+        buf
+    }
+
+    // optional_enum = TEN, repeated_bytes = ["ab"], map_message = {1: {optional_string: "x"}}
+    const COMPLEX: &[u8] = &[
+        0x08, 0x0a, 0x12, 0x02, 0x61, 0x62, 0x1a, 0x07, 0x08, 0x01, 0x12, 0x03, 0x0a, 0x01, 0x78,
+    ];
+
+    #[test]
+    fn heavy_round_trip() {
+        let m = heavy_complex();
+        assert_eq!(encoded(&m), COMPLEX);
+        assert_eq!(heavy::complex::Complex::decode(COMPLEX).unwrap(), m);
+
+        let mut simple = heavy::simple::Simple::new();
+        simple.simple_bool = true;
+        assert_eq!(encoded(&simple), [0x08, 0x01]);
+        assert_eq!(
+            heavy::simple::Simple::decode(&[0x08, 0x01]).unwrap(),
+            simple
+        );
+    }
+
+    #[test]
+    fn light_round_trip() {
+        let m = light::complex::Complex::from(heavy_complex());
+        assert_eq!(encoded(&m), COMPLEX);
+
+        let decoded = light::complex::Complex::decode(COMPLEX).unwrap();
+        assert_eq!(decoded.optional_enum(), light::complex::ComplexEnum::Ten);
+        assert_eq!(decoded.repeated_bytes(), &[b"ab".to_vec()]);
+        assert_eq!(decoded.map_message()[&1].optional_string(), "x");
+
+        let mut simple = light::simple::Simple::new();
+        *simple.simple_bool_mut() = true;
+        assert_eq!(encoded(&simple), [0x08, 0x01]);
+        assert!(light::simple::Simple::decode(&[0x08, 0x01])
+            .unwrap()
+            .simple_bool());
+    }
+
+    #[test]
+    fn light_types_lend_their_message() {
+        let m = light::complex::Complex::from(heavy_complex());
+        assert!(matches!(m.as_message(), Cow::Borrowed(_)));
+        assert!(matches!(heavy_complex().as_message(), Cow::Owned(_)));
+    }
+
+    #[test]
+    fn merge_from() {
+        let mut other = heavy::complex::Complex::new();
+        other.optional_enum = Some(heavy::complex::ComplexEnum::Two);
+        other.repeated_bytes = vec![b"cd".to_vec()];
+
+        let mut m = heavy_complex();
+        m.merge_from(&other);
+        assert_eq!(m.optional_enum, Some(heavy::complex::ComplexEnum::Two));
+        assert_eq!(m.repeated_bytes, [b"ab".to_vec(), b"cd".to_vec()]);
+        assert_eq!(m.map_message.len(), 1);
+
+        let mut light = light::complex::Complex::from(heavy_complex());
+        light.merge_from(&other.into());
+        assert_eq!(heavy::complex::Complex::from(light), m);
+    }
+
+    #[test]
+    fn decode_keeps_unknown_fields() {
+        let bytes = [COMPLEX, &[0x78, 0x01]].concat();
+        let m = heavy::complex::Complex::decode(&bytes).unwrap();
+        assert_eq!(m.unknown_fields, [0x78, 0x01]);
+        assert_eq!(encoded(&m), bytes);
+
+        let m = light::complex::Complex::decode(&bytes).unwrap();
+        assert_eq!(encoded(&m), bytes);
+    }
+
+    #[test]
+    fn decode_rejects_malformed_input() {
+        assert!(heavy::complex::Complex::decode(&COMPLEX[..COMPLEX.len() - 1]).is_err());
+        assert!(light::complex::Complex::decode(&[0x0a, 0x00]).is_err());
+    }
+
+    #[test]
+    fn clear() {
+        let mut m = heavy_complex();
+        m.clear();
+        assert_eq!(m, heavy::complex::Complex::new());
+        assert!(encoded(&m).is_empty());
+    }
+
+    #[test]
+    fn presence() {
+        // Implicit fields at their default value are not written.
+        let simple = heavy::simple::Simple::new();
+        assert!(encoded(&simple).is_empty());
+        assert!(encoded(&light::simple::Simple::from(simple)).is_empty());
+
+        // Explicit fields are written once set, even to their default value.
+        let mut m = heavy::complex::Complex::new();
+        assert!(!m.has_optional_enum());
+        m.optional_enum = Some(heavy::complex::ComplexEnum::default());
+        assert!(m.has_optional_enum());
+        assert_eq!(encoded(&m), [0x08, 0x01]);
+        m.clear_optional_enum();
+        assert!(!m.has_optional_enum());
+        assert!(encoded(&m).is_empty());
+
+        let mut nested = heavy::complex::ComplexNested::new();
+        nested.optional_string = Some(String::new());
+        assert!(nested.has_optional_string());
+        assert_eq!(encoded(&nested), [0x0a, 0x00]);
+        nested.clear_optional_string();
+        assert!(encoded(&nested).is_empty());
+
+        let mut nested = light::complex::ComplexNested::new();
+        nested.optional_string_mut();
+        assert!(nested.has_optional_string());
+        assert_eq!(encoded(&nested), [0x0a, 0x00]);
+        nested.clear_optional_string();
+        assert!(!nested.has_optional_string());
+        assert!(encoded(&nested).is_empty());
+    }
+
+    fn hash<T: Hash>(v: &T) -> u64 {
+        let mut h = DefaultHasher::new();
+        v.hash(&mut h);
+        h.finish()
+    }
+
+    #[test]
+    fn heavy_and_light_agree_on_equality() {
+        let mut other = heavy_complex();
+        other
+            .map_message
+            .insert(2, heavy::complex::ComplexNested::new());
+        let mut unknown = heavy_complex();
+        unknown.unknown_fields = vec![0x78, 0x01];
+
+        let cases = [
+            (
+                heavy::complex::Complex::new(),
+                heavy::complex::Complex::new(),
+            ),
+            (heavy_complex(), heavy_complex()),
+            (heavy_complex(), other),
+            (heavy_complex(), unknown),
+            (heavy_complex(), heavy::complex::Complex::new()),
+        ];
+        for (a, b) in cases {
+            let (x, y) = (
+                light::complex::Complex::from(a.clone()),
+                light::complex::Complex::from(b.clone()),
+            );
+            assert_eq!(a == b, x == y, "{:?} {:?}", a, b);
+            if a == b {
+                assert_eq!(hash(&a), hash(&b));
+                assert_eq!(hash(&x), hash(&y));
+            }
+        }
+    }
+
+    #[test]
+    fn unset_implicit_fields_equal_and_hash_as_their_default() {
+        let unset = light::simple::Simple::new();
+        let mut set = light::simple::Simple::new();
+        *set.simple_bool_mut() = false;
+        assert_eq!(unset, set);
+        assert_eq!(hash(&unset), hash(&set));
+        assert_eq!(
+            heavy::simple::Simple::from(unset),
+            heavy::simple::Simple::new()
+        );
+    }
+}
@@ -1,6 +1,28 @@
-use std::{convert::TryInto, marker::PhantomData};
+use std::{
+    borrow::Cow,
+    convert::TryInto,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    mem, ptr,
+};
 
-use crate::{error::AbsorbError, value::Message};
+use crate::{
+    codec, constraint,
+    descriptor::{FieldDescriptor, MessageDescriptor},
+    diff::{self, Diff},
+    eq,
+    error::{AbsorbError, ConstraintError, EncodeError},
+    field_mask::{self, FieldMask},
+    fingerprint,
+    list::ReflectList,
+    map::ReflectMap,
+    message::{self, ProtoMessage},
+    validate,
+    value::{Key, Map, Message, Rule, Value},
+};
+
+// Holds for every Reflection: it starts from a valid message and set checks what goes in.
+const VALID: &str = "invariant: a reflection holds a message matching its descriptor";
 
 pub trait Reflect: Sized {
     fn reflect(self) -> Reflection<Self>;
@@ -26,11 +48,139 @@ where
 
 impl<T> Reflection<T> {
     pub fn absorb(self) -> Result<T, AbsorbError>
+    where
+        T: ProtoMessage,
+    {
+        message::check_initialized(&self.message, T::descriptor())?;
+        self.message.try_into()
+    }
+
+    // Like absorb, but leaves required fields unchecked.
+    pub fn absorb_partial(self) -> Result<T, AbsorbError>
     where
         Message: TryInto<T, Error = AbsorbError>,
     {
         self.message.try_into()
     }
+
+    // Like absorb, but reports every violation in the message at once rather than only the
+    // first one found.
+    pub fn absorb_all(self) -> Result<T, AbsorbError>
+    where
+        T: ProtoMessage,
+    {
+        absorb_all(self.message)
+    }
+
+    /// Moves the message over to another type of the same schema, such as from a heavy to a light
+    /// type. Fields are matched by number, and a number with a different type on each side is an
+    /// error. Fields that U does not know about become unknown fields, and unknown fields that U
+    /// knows are parsed.
+    pub fn retarget<U>(self) -> Result<Reflection<U>, AbsorbError>
+    where
+        T: ProtoMessage,
+        U: ProtoMessage,
+    {
+        check_compatible(T::descriptor(), U::descriptor(), &mut Vec::new())?;
+        Ok(Reflection {
+            message: remap(self.message, T::descriptor(), U::descriptor())?,
+            _marker: PhantomData,
+        })
+    }
+}
+
+// NaN equals NaN here, as in message_eq, so the comparison is reflexive and agrees with Hash.
+impl<T: ProtoMessage> PartialEq for Reflection<T> {
+    fn eq(&self, other: &Self) -> bool {
+        eq::equal(
+            &self.message,
+            &other.message,
+            T::descriptor(),
+            &eq::REFLEXIVE,
+        )
+    }
+}
+
+impl<T: ProtoMessage> Eq for Reflection<T> {}
+
+impl<T: ProtoMessage> Hash for Reflection<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u128(self.fingerprint());
+    }
+}
+
+impl<T: ProtoMessage> Reflection<T> {
+    pub fn descriptor(&self) -> &'static MessageDescriptor {
+        T::descriptor()
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    pub fn fingerprint(&self) -> u128 {
+        fingerprint::fingerprint(&self.message, T::descriptor())
+    }
+
+    pub fn encoded_len(&self) -> Result<usize, EncodeError> {
+        codec::encoded_len(&self.message, T::descriptor())
+    }
+
+    pub fn check_constraints(&self) -> Result<(), ConstraintError> {
+        constraint::check(&self.message, T::descriptor())
+    }
+
+    pub fn merge_from(&mut self, other: &Reflection<T>) {
+        self.message
+            .merge_from(&other.message, T::descriptor())
+            .expect(VALID);
+    }
+
+    // The changes that turn this message into other.
+    pub fn diff(&self, other: &Reflection<T>) -> Diff {
+        diff::diff(&self.message, &other.message, T::descriptor()).expect(VALID)
+    }
+
+    // Clears every field outside the mask, and the unknown fields too.
+    pub fn prune(&mut self, mask: &FieldMask) -> Result<(), AbsorbError> {
+        field_mask::prune(&mut self.message, T::descriptor(), mask)
+    }
+
+    pub fn merge_with_mask(
+        &mut self,
+        other: &Reflection<T>,
+        mask: &FieldMask,
+    ) -> Result<(), AbsorbError> {
+        field_mask::merge_with_mask(&mut self.message, &other.message, T::descriptor(), mask)
+    }
+
+    pub fn get<K: FieldKey>(&self, key: K) -> Option<&Value> {
+        get(&self.message, T::descriptor(), key)
+    }
+
+    pub fn has<K: FieldKey>(&self, key: K) -> bool {
+        has(&self.message, T::descriptor(), key)
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&'static FieldDescriptor, &Value)> {
+        fields(&self.message, T::descriptor())
+    }
+
+    pub fn set<K: FieldKey>(&mut self, key: K, value: Value) -> Result<(), AbsorbError> {
+        set(&mut self.message, T::descriptor(), key, value)
+    }
+
+    pub fn clear<K: FieldKey>(&mut self, key: K) {
+        clear(&mut self.message, T::descriptor(), key)
+    }
+
+    pub fn list_mut<K: FieldKey>(&mut self, key: K) -> Option<ReflectList<'_>> {
+        list_mut(&mut self.message, T::descriptor(), key)
+    }
+
+    pub fn map_mut<K: FieldKey>(&mut self, key: K) -> Option<ReflectMap<'_>> {
+        map_mut(&mut self.message, T::descriptor(), key)
+    }
 }
 
 impl<T> Reflect for T
@@ -41,3 +191,603 @@ where
         Reflection::new(self)
     }
 }
+
+/// Reflection without giving up ownership. Types backed by a Message lend it directly. Others
+/// copy: reflect_ref reads from a converted clone, and reflect_mut edits a converted clone and
+/// writes it back when the handle is dropped, or through `finish`, which returns the error if the
+/// concrete type rejects the result. A rejected result leaves the value as it was.
+///
+/// Fields are named by their `FieldDescriptor`, name or number. `set` checks the value against the
+/// descriptor, and `list_mut` and `map_mut` edit repeated and map fields without matching on every
+/// `Value` variant.
+///
+/// ```
+/// use tobu_conversion::{
+///     heavy::simple::Simple,
+///     reflect::ReflectBorrow,
+///     value::{Rule, Value},
+/// };
+///
+/// let mut m = Simple::default();
+/// m.reflect_mut()
+///     .set("simple_bool", Value::Bool(Rule::Singular(true)))
+///     .unwrap();
+/// assert!(m.simple_bool);
+/// assert!(m.reflect_ref().has(1));
+/// ```
+pub trait ReflectBorrow: ProtoMessage {
+    fn reflect_ref(&self) -> ReflectionRef<'_, Self> {
+        ReflectionRef {
+            message: self.as_message(),
+            _marker: PhantomData,
+        }
+    }
+
+    fn reflect_mut(&mut self) -> ReflectionMut<'_, Self> {
+        ReflectionMut::adapted(self)
+    }
+}
+
+/// Read access to a message through its descriptor. It borrows the Message behind light types,
+/// but holds a converted copy for heavy and derived types, which costs as much as cloning them.
+#[derive(Debug)]
+pub struct ReflectionRef<'a, T> {
+    message: Cow<'a, Message>,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T: ProtoMessage> ReflectionRef<'a, T> {
+    pub fn borrowed(message: &'a Message) -> Self {
+        ReflectionRef {
+            message: Cow::Borrowed(message),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn owned(message: Message) -> Self {
+        ReflectionRef {
+            message: Cow::Owned(message),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn descriptor(&self) -> &'static MessageDescriptor {
+        T::descriptor()
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    pub(crate) fn into_message(self) -> Cow<'a, Message> {
+        self.message
+    }
+
+    pub fn check_constraints(&self) -> Result<(), ConstraintError> {
+        constraint::check(&self.message, T::descriptor())
+    }
+
+    pub fn get<K: FieldKey>(&self, key: K) -> Option<&Value> {
+        get(&self.message, T::descriptor(), key)
+    }
+
+    pub fn has<K: FieldKey>(&self, key: K) -> bool {
+        has(&self.message, T::descriptor(), key)
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&'static FieldDescriptor, &Value)> {
+        fields(&self.message, T::descriptor())
+    }
+}
+
+#[derive(Debug)]
+enum Lent<'a, T: ProtoMessage> {
+    Borrowed(&'a mut Message),
+    // Converted from a copy of the concrete value, which is replaced when the handle is finished or
+    // dropped and keeps its original contents if the message cannot be converted back.
+    Adapted {
+        concrete: &'a mut T,
+        message: Message,
+    },
+}
+
+#[derive(Debug)]
+pub struct ReflectionMut<'a, T: ProtoMessage> {
+    lent: Lent<'a, T>,
+}
+
+impl<'a, T: ProtoMessage> ReflectionMut<'a, T> {
+    pub fn borrowed(message: &'a mut Message) -> Self {
+        ReflectionMut {
+            lent: Lent::Borrowed(message),
+        }
+    }
+
+    pub fn adapted(concrete: &'a mut T) -> Self {
+        let message = concrete.clone().into();
+        ReflectionMut {
+            lent: Lent::Adapted { concrete, message },
+        }
+    }
+
+    pub fn descriptor(&self) -> &'static MessageDescriptor {
+        T::descriptor()
+    }
+
+    pub fn message(&self) -> &Message {
+        match &self.lent {
+            Lent::Borrowed(m) => m,
+            Lent::Adapted { message, .. } => message,
+        }
+    }
+
+    fn message_mut(&mut self) -> &mut Message {
+        match &mut self.lent {
+            Lent::Borrowed(m) => m,
+            Lent::Adapted { message, .. } => message,
+        }
+    }
+
+    pub fn check_constraints(&self) -> Result<(), ConstraintError> {
+        constraint::check(self.message(), T::descriptor())
+    }
+
+    pub fn get<K: FieldKey>(&self, key: K) -> Option<&Value> {
+        get(self.message(), T::descriptor(), key)
+    }
+
+    pub fn has<K: FieldKey>(&self, key: K) -> bool {
+        has(self.message(), T::descriptor(), key)
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&'static FieldDescriptor, &Value)> {
+        fields(self.message(), T::descriptor())
+    }
+
+    pub fn set<K: FieldKey>(&mut self, key: K, value: Value) -> Result<(), AbsorbError> {
+        set(self.message_mut(), T::descriptor(), key, value)
+    }
+
+    pub fn clear<K: FieldKey>(&mut self, key: K) {
+        clear(self.message_mut(), T::descriptor(), key)
+    }
+
+    pub fn list_mut<K: FieldKey>(&mut self, key: K) -> Option<ReflectList<'_>> {
+        list_mut(self.message_mut(), T::descriptor(), key)
+    }
+
+    pub fn map_mut<K: FieldKey>(&mut self, key: K) -> Option<ReflectMap<'_>> {
+        map_mut(self.message_mut(), T::descriptor(), key)
+    }
+
+    /// Writes the changes back, reporting a message the concrete type rejects. The value keeps its
+    /// original contents then. Dropping the handle writes back too, but discards rejected changes
+    /// silently.
+    pub fn finish(mut self) -> Result<(), AbsorbError> {
+        let result = self.write_back();
+        // Nothing is left to write back, and the emptied message owns no allocations.
+        mem::forget(self);
+        result
+    }
+
+    fn write_back(&mut self) -> Result<(), AbsorbError> {
+        if let Lent::Adapted { concrete, message } = &mut self.lent {
+            let message = mem::replace(
+                message,
+                Message {
+                    fields: Vec::new(),
+                    unknown_fields: Vec::new(),
+                },
+            );
+            **concrete = T::try_from(message)?;
+        }
+        Ok(())
+    }
+}
+
+// Panicking here would abort the process during unwinding, so a message the concrete type rejects
+// is dropped and the value keeps its original contents. Use finish to see the error.
+impl<'a, T: ProtoMessage> Drop for ReflectionMut<'a, T> {
+    fn drop(&mut self) {
+        let _ = self.write_back();
+    }
+}
+
+// Identifies a field of a message by its descriptor, name or number.
+pub trait FieldKey {
+    fn find(&self, d: &'static MessageDescriptor) -> Option<(usize, &'static FieldDescriptor)>;
+    // Used in errors when the key does not name a field.
+    fn describe(&self) -> String;
+}
+
+impl FieldKey for &FieldDescriptor {
+    fn find(&self, d: &'static MessageDescriptor) -> Option<(usize, &'static FieldDescriptor)> {
+        d.field_by_number(self.number)
+            .filter(|(_, f)| f.name == self.name)
+    }
+
+    fn describe(&self) -> String {
+        self.name.to_string()
+    }
+}
+
+impl FieldKey for &str {
+    fn find(&self, d: &'static MessageDescriptor) -> Option<(usize, &'static FieldDescriptor)> {
+        d.field_by_name(self)
+    }
+
+    fn describe(&self) -> String {
+        (*self).to_string()
+    }
+}
+
+impl FieldKey for u32 {
+    fn find(&self, d: &'static MessageDescriptor) -> Option<(usize, &'static FieldDescriptor)> {
+        d.field_by_number(*self)
+    }
+
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+}
+
+fn get<'a, K: FieldKey>(
+    m: &'a Message,
+    d: &'static MessageDescriptor,
+    key: K,
+) -> Option<&'a Value> {
+    let (i, _) = key.find(d)?;
+    m.fields[i].as_ref()
+}
+
+// Follows protoreflect: fields without presence only count when they differ from the default.
+fn has<K: FieldKey>(m: &Message, d: &'static MessageDescriptor, key: K) -> bool {
+    match key.find(d) {
+        Some((i, f)) => is_populated(&m.fields[i], f),
+        None => false,
+    }
+}
+
+fn is_populated(slot: &Option<Value>, f: &FieldDescriptor) -> bool {
+    match slot {
+        Some(v) => f.has_presence() || !v.is_default(),
+        None => false,
+    }
+}
+
+fn fields<'a>(
+    m: &'a Message,
+    d: &'static MessageDescriptor,
+) -> impl Iterator<Item = (&'static FieldDescriptor, &'a Value)> {
+    m.fields
+        .iter()
+        .zip(d.fields)
+        .filter(|(v, f)| is_populated(v, f))
+        .filter_map(|(v, f)| v.as_ref().map(|v| (f, v)))
+}
+
+fn set<K: FieldKey>(
+    m: &mut Message,
+    d: &'static MessageDescriptor,
+    key: K,
+    value: Value,
+) -> Result<(), AbsorbError> {
+    let (i, f) = key
+        .find(d)
+        .ok_or_else(|| AbsorbError::unknown_field(d.name, &key.describe()))?;
+    validate::check_value(&value, f)?;
+    m.fields[i] = Some(value);
+    Ok(())
+}
+
+// Unsets a singular field or empties a repeated one.
+fn clear<K: FieldKey>(m: &mut Message, d: &'static MessageDescriptor, key: K) {
+    if let Some((i, f)) = key.find(d) {
+        m.fields[i] = Value::empty(f);
+    }
+}
+
+fn list_mut<'a, K: FieldKey>(
+    m: &'a mut Message,
+    d: &'static MessageDescriptor,
+    key: K,
+) -> Option<ReflectList<'a>> {
+    let (i, f) = key.find(d)?;
+    ReflectList::new(f, m.fields[i].get_or_insert(Value::empty(f)?))
+}
+
+fn map_mut<'a, K: FieldKey>(
+    m: &'a mut Message,
+    d: &'static MessageDescriptor,
+    key: K,
+) -> Option<ReflectMap<'a>> {
+    let (i, f) = key.find(d)?;
+    ReflectMap::new(f, m.fields[i].get_or_insert(Value::empty(f)?))
+}
+
+fn check_compatible(
+    from: &'static MessageDescriptor,
+    to: &'static MessageDescriptor,
+    seen: &mut Vec<(&'static MessageDescriptor, &'static MessageDescriptor)>,
+) -> Result<(), AbsorbError> {
+    // Recursive schemas would otherwise be visited forever.
+    if ptr::eq(from, to)
+        || seen
+            .iter()
+            .any(|(a, b)| ptr::eq(*a, from) && ptr::eq(*b, to))
+    {
+        return Ok(());
+    }
+    seen.push((from, to));
+
+    for f in from.fields {
+        let g = match to.field_by_number(f.number) {
+            Some((_, g)) => g,
+            None => continue,
+        };
+        if f.ty != g.ty || f.label != g.label || f.presence != g.presence || f.key != g.key {
+            return Err(AbsorbError::incompatible(f.name, from.name, to.name));
+        }
+        // Open and closed enums disagree on where unknown values go.
+        match (f.enum_descriptor(), g.enum_descriptor()) {
+            (Some(a), Some(b)) if a.closed == b.closed => {}
+            (None, None) => {}
+            _ => return Err(AbsorbError::incompatible(f.name, from.name, to.name)),
+        }
+        match (f.message_descriptor(), g.message_descriptor()) {
+            (Some(a), Some(b)) => check_compatible(a, b, seen)?,
+            (None, None) => {}
+            _ => return Err(AbsorbError::incompatible(f.name, from.name, to.name)),
+        }
+    }
+    Ok(())
+}
+
+fn remap(
+    m: Message,
+    from: &MessageDescriptor,
+    to: &'static MessageDescriptor,
+) -> Result<Message, AbsorbError> {
+    if ptr::eq(from, to) {
+        return Ok(m);
+    }
+
+    let mut out = Message::new(to);
+    for (v, f) in m.fields.into_iter().zip(from.fields) {
+        let v = match v {
+            Some(v) => v,
+            None => continue,
+        };
+        match to.field_by_number(f.number) {
+            Some((i, g)) => out.fields[i] = Some(remap_value(v, f, g)?),
+            None => codec::encode_field(&v, f, &mut out.unknown_fields)
+                .map_err(|_| AbsorbError::invalid_type(f, &v))?,
+        }
+    }
+
+    // The target may know about some of the unknown fields. Bytes that do not parse are kept
+    // as they are.
+    let unknown = [m.unknown_fields, mem::take(&mut out.unknown_fields)].concat();
+    match codec::decode(&unknown, to) {
+        Ok(decoded) => out
+            .merge_from(&decoded, to)
+            .expect("invariant: remapped fields match the target descriptor"),
+        Err(_) => out.unknown_fields = unknown,
+    }
+    Ok(out)
+}
+
+fn remap_value(v: Value, f: &FieldDescriptor, g: &FieldDescriptor) -> Result<Value, AbsorbError> {
+    let (rule, from, to) = match (v, f.message_descriptor(), g.message_descriptor()) {
+        (Value::Message(rule), Some(from), Some(to)) => (rule, from, to),
+        (v, _, _) => return Ok(v),
+    };
+
+    let remap = |m| remap(m, from, to);
+    Ok(Value::Message(match rule {
+        Rule::Singular(m) => Rule::Singular(remap(m)?),
+        Rule::Repeated(v) => Rule::Repeated(v.into_iter().map(remap).collect::<Result<_, _>>()?),
+        Rule::Map(k) => Rule::Map(match k {
+            Key::Bool(v) => Key::Bool(remap_entries(v, remap)?),
+            Key::I32(v) => Key::I32(remap_entries(v, remap)?),
+            Key::I64(v) => Key::I64(remap_entries(v, remap)?),
+            Key::String(v) => Key::String(remap_entries(v, remap)?),
+            Key::U32(v) => Key::U32(remap_entries(v, remap)?),
+            Key::U64(v) => Key::U64(remap_entries(v, remap)?),
+        }),
+    }))
+}
+
+fn remap_entries<K: Ord + Hash>(
+    m: Map<K, Message>,
+    remap: impl Fn(Message) -> Result<Message, AbsorbError>,
+) -> Result<Map<K, Message>, AbsorbError> {
+    m.into_iter().map(|(k, v)| Ok((k, remap(v)?))).collect()
+}
+
+// Converts a message into T, reporting every violation in it rather than only the first one.
+pub fn absorb_all<T: ProtoMessage>(m: Message) -> Result<T, AbsorbError> {
+    let mut errors = validate::validate_all(&m, T::descriptor())
+        .map_or_else(AbsorbError::into_errors, |_| Vec::new());
+    let missing = message::missing_required_fields(&m, T::descriptor());
+    if !missing.is_empty() {
+        errors.push(AbsorbError::missing_required(missing));
+    }
+    // Conversions may accept more than the walk does, e.g. heavy types move unknown closed enum
+    // values into the unknown fields, so the report is only used if the conversion fails.
+    message::check_initialized(&m, T::descriptor())
+        .and_then(|_| m.try_into())
+        .map_err(|e| AbsorbError::multiple(errors).unwrap_or(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::{
+        descriptor::{Label, Presence, Type},
+        heavy, light,
+    };
+
+    // Holds an even number, which its descriptor alone cannot express.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Even(i32);
+
+    impl ProtoMessage for Even {
+        fn descriptor() -> &'static MessageDescriptor {
+            static DESCRIPTOR: MessageDescriptor = MessageDescriptor {
+                name: "Even",
+                fields: &[FieldDescriptor {
+                    name: "value",
+                    number: 1,
+                    ty: Type::Int32,
+                    label: Label::Optional,
+                    presence: Presence::Implicit,
+                    key: None,
+                    message: None,
+                    enumeration: None,
+                    constraints: &[],
+                }],
+            };
+            &DESCRIPTOR
+        }
+
+        fn as_message(&self) -> Cow<'_, Message> {
+            Cow::Owned(self.clone().into())
+        }
+    }
+
+    impl ReflectBorrow for Even {}
+
+    impl From<Even> for Message {
+        fn from(e: Even) -> Self {
+            Message {
+                fields: vec![Some(Value::I32(Rule::Singular(e.0)))],
+                unknown_fields: Vec::new(),
+            }
+        }
+    }
+
+    impl TryFrom<Message> for Even {
+        type Error = AbsorbError;
+
+        fn try_from(m: Message) -> Result<Self, Self::Error> {
+            match m.fields.as_slice() {
+                [Some(Value::I32(Rule::Singular(v)))] if v % 2 == 0 => Ok(Even(*v)),
+                [None] => Ok(Even(0)),
+                [Some(v)] => Err(AbsorbError::invalid_type(&Self::descriptor().fields[0], v)),
+                _ => Err(AbsorbError::invalid_length(1, m.fields.len())),
+            }
+        }
+    }
+
+    // Holds a double, to check how reflections compare NaN.
+    #[derive(Debug, Clone, Default)]
+    struct Ratio(f64);
+
+    impl ProtoMessage for Ratio {
+        fn descriptor() -> &'static MessageDescriptor {
+            static DESCRIPTOR: MessageDescriptor = MessageDescriptor {
+                name: "Ratio",
+                fields: &[FieldDescriptor {
+                    name: "value",
+                    number: 1,
+                    ty: Type::Double,
+                    label: Label::Optional,
+                    presence: Presence::Implicit,
+                    key: None,
+                    message: None,
+                    enumeration: None,
+                    constraints: &[],
+                }],
+            };
+            &DESCRIPTOR
+        }
+
+        fn as_message(&self) -> Cow<'_, Message> {
+            Cow::Owned(self.clone().into())
+        }
+    }
+
+    impl From<Ratio> for Message {
+        fn from(r: Ratio) -> Self {
+            Message {
+                fields: vec![Some(Value::F64(Rule::Singular(r.0)))],
+                unknown_fields: Vec::new(),
+            }
+        }
+    }
+
+    impl TryFrom<Message> for Ratio {
+        type Error = AbsorbError;
+
+        fn try_from(m: Message) -> Result<Self, Self::Error> {
+            match m.fields.as_slice() {
+                [Some(Value::F64(Rule::Singular(v)))] => Ok(Ratio(*v)),
+                [None] => Ok(Ratio(0.0)),
+                [Some(v)] => Err(AbsorbError::invalid_type(&Self::descriptor().fields[0], v)),
+                _ => Err(AbsorbError::invalid_length(1, m.fields.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn reflections_with_nan_equal_themselves() {
+        let r = Ratio(f64::NAN).reflect();
+        assert_eq!(r, r.clone());
+        assert_ne!(r, Ratio(1.0).reflect());
+    }
+
+    #[test]
+    fn reflect_ref_borrows_light_types_and_converts_a_copy_of_heavy_ones() {
+        let m = light::simple::Simple::new();
+        assert!(ptr::eq(m.reflect_ref().message(), m.as_message().as_ref()));
+        assert!(matches!(
+            heavy::simple::Simple::new().reflect_ref().into_message(),
+            Cow::Owned(_)
+        ));
+    }
+
+    #[test]
+    fn finish_writes_back() {
+        let mut m = heavy::simple::Simple::new();
+        let mut r = m.reflect_mut();
+        r.set("simple_bool", Value::Bool(Rule::Singular(true)))
+            .unwrap();
+        r.finish().unwrap();
+        assert!(m.simple_bool);
+
+        let mut e = Even(2);
+        let mut r = e.reflect_mut();
+        r.set("value", Value::I32(Rule::Singular(4))).unwrap();
+        r.finish().unwrap();
+        assert_eq!(e, Even(4));
+    }
+
+    #[test]
+    fn finish_reports_values_the_concrete_type_rejects() {
+        let mut e = Even(2);
+        let mut r = e.reflect_mut();
+        r.set("value", Value::I32(Rule::Singular(3))).unwrap();
+        assert!(r.finish().is_err());
+        assert_eq!(e, Even(2));
+    }
+
+    #[test]
+    fn drop_keeps_the_original_when_the_concrete_type_rejects_the_changes() {
+        let mut e = Even(2);
+        let mut r = e.reflect_mut();
+        r.set("value", Value::I32(Rule::Singular(3))).unwrap();
+        drop(r);
+        assert_eq!(e, Even(2));
+
+        // Unwinding through a handle holding rejected changes does not abort.
+        let result = std::panic::catch_unwind(move || {
+            let mut r = e.reflect_mut();
+            r.set("value", Value::I32(Rule::Singular(5))).unwrap();
+            panic!("while the handle is alive");
+        });
+        assert!(result.is_err());
+    }
+}
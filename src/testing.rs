@@ -0,0 +1,470 @@
+use std::{borrow::Cow, hash::Hash};
+
+use crate::{
+    codec::{self, EncodeOptions},
+    descriptor::{FieldDescriptor, Label, MessageDescriptor},
+    diff,
+    eq::{self, EqOptions, NanPolicy},
+    error::{DescriptorError, MatchError},
+    message::ProtoMessage,
+    reflect::{ReflectBorrow, Reflection, ReflectionRef},
+    validate,
+    value::{Key, Map, Message, Rule, Value},
+};
+
+/// Compares two messages of the same type and panics with a field by field diff when they differ,
+/// rather than with the Debug output of both. An optional third argument is a Matcher.
+///
+/// ```
+/// use tobu_conversion::{
+///     assert_proto_eq,
+///     heavy::complex::{Complex, ComplexEnum},
+///     testing::Matcher,
+/// };
+///
+/// let actual = Complex {
+///     optional_enum: Some(ComplexEnum::One),
+///     repeated_bytes: vec![b"b".to_vec(), b"a".to_vec()],
+///     ..Complex::default()
+/// };
+/// let expected = Complex {
+///     repeated_bytes: vec![b"a".to_vec(), b"b".to_vec()],
+///     ..Complex::default()
+/// };
+/// assert_proto_eq!(
+///     actual,
+///     expected,
+///     Matcher::new().contains().unordered("repeated_bytes")
+/// );
+/// ```
+#[macro_export]
+macro_rules! assert_proto_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::testing::assert_proto_eq(&$left, &$right, &$crate::testing::Matcher::new())
+    };
+    ($left:expr, $right:expr, $matcher:expr $(,)?) => {
+        $crate::testing::assert_proto_eq(&$left, &$right, &$matcher)
+    };
+}
+
+/// Encodes a message with map entries in key order, the way tests comparing bytes need it. Plain
+/// `encode` writes them in the order the map iterates, which a HashMap changes from run to run.
+pub fn encode<S: Subject>(m: &S) -> Vec<u8> {
+    let (m, d) = m.proto();
+    let options = EncodeOptions {
+        deterministic: true,
+    };
+    let mut buf = Vec::new();
+    codec::encode_with(&m, d, &options, &mut buf)
+        .expect("invariant: a Subject holds a message matching its descriptor");
+    buf
+}
+
+#[track_caller]
+pub fn assert_proto_eq<S: Subject>(left: &S, right: &S, matcher: &Matcher) {
+    if let Err(e) = matcher.check(left, right) {
+        panic!("{}", e)
+    }
+}
+
+// Anything assert_proto_eq! can compare: heavy, light and derived messages, and reflections.
+pub trait Subject {
+    fn proto(&self) -> (Cow<'_, Message>, &'static MessageDescriptor);
+}
+
+impl<T: ReflectBorrow> Subject for T {
+    fn proto(&self) -> (Cow<'_, Message>, &'static MessageDescriptor) {
+        (
+            self.reflect_ref().into_message(),
+            <T as ProtoMessage>::descriptor(),
+        )
+    }
+}
+
+impl<T: ProtoMessage> Subject for Reflection<T> {
+    fn proto(&self) -> (Cow<'_, Message>, &'static MessageDescriptor) {
+        (Cow::Borrowed(self.message()), T::descriptor())
+    }
+}
+
+impl<'a, T: ProtoMessage> Subject for ReflectionRef<'a, T> {
+    fn proto(&self) -> (Cow<'_, Message>, &'static MessageDescriptor) {
+        (Cow::Borrowed(self.message()), T::descriptor())
+    }
+}
+
+/// How two messages are compared. By default every field and the unknown fields must be equal,
+/// with protobuf semantics and NaN equal to NaN. Paths are dot separated field names and pass
+/// through repeated and map message fields, so `map_message.optional_string` names the field in
+/// every entry.
+#[derive(Debug, Clone, Default)]
+pub struct Matcher {
+    ignored: Vec<String>,
+    unordered: Vec<String>,
+    contains: bool,
+    ignore_unknown_fields: bool,
+}
+
+impl Matcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skips the field at the end of the path in every message along it.
+    pub fn ignore(mut self, path: &str) -> Self {
+        self.ignored.push(path.to_owned());
+        self
+    }
+
+    /// Compares a repeated field as a multiset, regardless of the order of its elements.
+    pub fn unordered(mut self, path: &str) -> Self {
+        self.unordered.push(path.to_owned());
+        self
+    }
+
+    /// Only compares the fields set in the right message, which lists what the left must contain.
+    /// Nested messages set on both sides are matched the same way.
+    pub fn contains(mut self) -> Self {
+        self.contains = true;
+        self
+    }
+
+    pub fn ignore_unknown_fields(mut self) -> Self {
+        self.ignore_unknown_fields = true;
+        self
+    }
+
+    /// Ok when the messages match, otherwise a report of what differs. Every path is checked
+    /// against the descriptor before anything is compared.
+    pub fn check<S: Subject>(&self, left: &S, right: &S) -> Result<(), MatchError> {
+        let (left, d) = left.proto();
+        let (right, _) = right.proto();
+        let ignored = self
+            .ignored
+            .iter()
+            .map(|path| resolve(d, path, false))
+            .collect::<Result<Vec<_>, _>>()?;
+        let unordered = self
+            .unordered
+            .iter()
+            .map(|path| resolve(d, path, true))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut left = left.into_owned();
+        let mut right = right.into_owned();
+
+        if self.contains {
+            retain_expected(&mut left, &right, d);
+        }
+        for segments in &ignored {
+            for m in [&mut left, &mut right] {
+                clear(m, d, segments);
+            }
+        }
+        let options = EqOptions {
+            nan: NanPolicy::Equal,
+            ignore_unknown_fields: self.ignore_unknown_fields,
+        };
+        for segments in &unordered {
+            reorder(&mut left, &right, d, segments, &options);
+        }
+
+        if eq::equal(&left, &right, d, &options) {
+            return Ok(());
+        }
+        let report = match diff::diff(&left, &right, d) {
+            Ok(diff) if diff.is_empty() => "unknown fields differ\n".to_owned(),
+            Ok(diff) => diff.to_string(),
+            Err(e) => format!("{}\n", e),
+        };
+        Err(MatchError::mismatch(d.name, report))
+    }
+}
+
+// The fields along a path. Only the last may be a scalar, and it must be repeated if wanted.
+fn resolve<'p>(
+    d: &MessageDescriptor,
+    path: &'p str,
+    repeated: bool,
+) -> Result<Vec<&'p str>, DescriptorError> {
+    let segments: Vec<_> = path.split('.').collect();
+    let mut d = d;
+    for (i, segment) in segments.iter().enumerate() {
+        let (_, f) = d
+            .field_by_name(segment)
+            .ok_or_else(|| DescriptorError::unknown_field(d.name, segment))?;
+        if i + 1 == segments.len() {
+            if repeated && (f.label != Label::Repeated || f.is_map()) {
+                return Err(DescriptorError::invalid_path(path));
+            }
+            break;
+        }
+        d = f
+            .message_descriptor()
+            .ok_or_else(|| DescriptorError::invalid_path(path))?;
+    }
+    Ok(segments)
+}
+
+// Clears every field of left that is unset in right. Fields without presence count as unset
+// when they hold their default.
+fn retain_expected(left: &mut Message, right: &Message, d: &MessageDescriptor) {
+    for ((l, r), f) in left.fields.iter_mut().zip(&right.fields).zip(d.fields) {
+        match (l, r) {
+            (l, None) => *l = None,
+            (l, Some(r)) if !f.has_presence() && r.is_default() => *l = None,
+            (Some(Value::Message(Rule::Singular(l))), Some(Value::Message(Rule::Singular(r)))) => {
+                if let Some(d) = f.message_descriptor() {
+                    retain_expected(l, r, d);
+                }
+            }
+            _ => {}
+        }
+    }
+    if right.unknown_fields.is_empty() {
+        left.unknown_fields.clear();
+    }
+}
+
+fn clear(m: &mut Message, d: &MessageDescriptor, segments: &[&str]) {
+    let (i, f) = match d.field_by_name(segments[0]) {
+        Some(found) => found,
+        None => return,
+    };
+    match (&segments[1..], m.fields.get_mut(i), f.message_descriptor()) {
+        ([], Some(v), _) => *v = None,
+        (rest, Some(Some(Value::Message(rule))), Some(d)) => {
+            for m in rule.iter_mut() {
+                clear(m, d, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Puts the elements of the repeated field at the end of the path in left into the order of the
+// equal elements in right, leaving the rest at the end. Nested messages are paired up by index
+// or key.
+fn reorder(
+    left: &mut Message,
+    right: &Message,
+    d: &MessageDescriptor,
+    segments: &[&str],
+    options: &EqOptions,
+) {
+    let (i, f) = match d.field_by_name(segments[0]) {
+        Some(found) => found,
+        None => return,
+    };
+    let (l, r) = match (left.fields.get_mut(i), right.fields.get(i)) {
+        (Some(Some(l)), Some(Some(r))) => (l, r),
+        _ => return,
+    };
+    if segments.len() == 1 {
+        let order = matching_order(l, r, f, options);
+        permute(l, &order);
+        return;
+    }
+
+    let d = match f.message_descriptor() {
+        Some(d) => d,
+        None => return,
+    };
+    let pair = |l: &mut Message, r: &Message| reorder(l, r, d, &segments[1..], options);
+    match (l, r) {
+        (Value::Message(Rule::Singular(l)), Value::Message(Rule::Singular(r))) => pair(l, r),
+        (Value::Message(Rule::Repeated(l)), Value::Message(Rule::Repeated(r))) => {
+            for (l, r) in l.iter_mut().zip(r) {
+                pair(l, r);
+            }
+        }
+        (Value::Message(Rule::Map(l)), Value::Message(Rule::Map(r))) => match (l, r) {
+            (Key::Bool(l), Key::Bool(r)) => pair_entries(l, r, pair),
+            (Key::I32(l), Key::I32(r)) => pair_entries(l, r, pair),
+            (Key::I64(l), Key::I64(r)) => pair_entries(l, r, pair),
+            (Key::String(l), Key::String(r)) => pair_entries(l, r, pair),
+            (Key::U32(l), Key::U32(r)) => pair_entries(l, r, pair),
+            (Key::U64(l), Key::U64(r)) => pair_entries(l, r, pair),
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+fn pair_entries<K: Ord + Hash>(
+    l: &mut Map<K, Message>,
+    r: &Map<K, Message>,
+    mut pair: impl FnMut(&mut Message, &Message),
+) {
+    for (k, l) in l {
+        if let Some(r) = r.get(k) {
+            pair(l, r);
+        }
+    }
+}
+
+// Indices of the elements of l, first those matching the elements of r in turn, then the rest.
+fn matching_order(l: &Value, r: &Value, f: &FieldDescriptor, options: &EqOptions) -> Vec<usize> {
+    let l = validate::element_refs(l);
+    let mut used = vec![false; l.len()];
+    let mut order = Vec::with_capacity(l.len());
+    for (_, y) in validate::element_refs(r) {
+        let found = (0..l.len()).find(|&i| !used[i] && eq::equal_elements(l[i].1, y, f, options));
+        if let Some(i) = found {
+            used[i] = true;
+            order.push(i);
+        }
+    }
+    order.extend((0..l.len()).filter(|&i| !used[i]));
+    order
+}
+
+fn permute(v: &mut Value, order: &[usize]) {
+    match v {
+        Value::Bool(rule) => permute_rule(rule, order),
+        Value::Bytes(rule) => permute_rule(rule, order),
+        Value::Enum(rule) => permute_rule(rule, order),
+        Value::F32(rule) => permute_rule(rule, order),
+        Value::F64(rule) => permute_rule(rule, order),
+        Value::I32(rule) => permute_rule(rule, order),
+        Value::I64(rule) => permute_rule(rule, order),
+        Value::Message(rule) => permute_rule(rule, order),
+        Value::String(rule) => permute_rule(rule, order),
+        Value::U32(rule) => permute_rule(rule, order),
+        Value::U64(rule) => permute_rule(rule, order),
+    }
+}
+
+// Moves element order[i] to position i. Order holds every index once, as matching_order returns.
+fn permute_rule<T>(rule: &mut Rule<T>, order: &[usize]) {
+    if let Rule::Repeated(v) = rule {
+        let mut position = vec![0; order.len()];
+        for (to, &from) in order.iter().enumerate() {
+            position[from] = to;
+        }
+        let mut moved: Vec<_> = position.into_iter().zip(v.drain(..)).collect();
+        moved.sort_unstable_by_key(|&(to, _)| to);
+        v.extend(moved.into_iter().map(|(_, x)| x));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::ErrorKind,
+        heavy::complex::{Complex, ComplexEnum, ComplexNested},
+        reflect::Reflect,
+    };
+
+    fn nested(s: &str) -> ComplexNested {
+        ComplexNested {
+            optional_string: Some(s.to_owned()),
+            ..ComplexNested::default()
+        }
+    }
+
+    fn complex(bytes: &[&str], entries: &[(i32, &str)]) -> Complex {
+        Complex {
+            optional_enum: Some(ComplexEnum::One),
+            repeated_bytes: bytes.iter().map(|b| b.as_bytes().to_vec()).collect(),
+            map_message: entries.iter().map(|&(k, s)| (k, nested(s))).collect(),
+            ..Complex::default()
+        }
+    }
+
+    fn mismatch(matcher: &Matcher, left: &Complex, right: &Complex) -> bool {
+        match matcher.check(left, right) {
+            Ok(()) => false,
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::Mismatch, "{}", e);
+                true
+            }
+        }
+    }
+
+    #[test]
+    fn ignore() {
+        let left = complex(&["a"], &[(1, "x")]);
+        let right = complex(&["a"], &[(1, "y")]);
+        assert!(mismatch(&Matcher::new(), &left, &right));
+        assert!(!mismatch(
+            &Matcher::new().ignore("map_message"),
+            &left,
+            &right
+        ));
+        let matcher = Matcher::new().ignore("map_message.optional_string");
+        assert!(!mismatch(&matcher, &left, &right));
+
+        // Fields outside the ignored path still count.
+        let right = complex(&["b"], &[(1, "y")]);
+        assert!(mismatch(&matcher, &left, &right));
+    }
+
+    #[test]
+    fn unordered() {
+        let matcher = Matcher::new().unordered("repeated_bytes");
+        let left = complex(&["a", "b", "a"], &[]);
+        assert!(!mismatch(&matcher, &left, &complex(&["b", "a", "a"], &[])));
+        assert!(mismatch(
+            &Matcher::new(),
+            &left,
+            &complex(&["b", "a", "a"], &[])
+        ));
+
+        // The same elements, counted with multiplicity.
+        assert!(mismatch(&matcher, &left, &complex(&["a", "b", "b"], &[])));
+        assert!(mismatch(&matcher, &left, &complex(&["a", "b"], &[])));
+    }
+
+    #[test]
+    fn contains() {
+        let left = complex(&["a"], &[(1, "x"), (2, "y")]);
+        let expected = Complex {
+            optional_enum: Some(ComplexEnum::One),
+            ..Complex::default()
+        };
+        assert!(!mismatch(&Matcher::new().contains(), &left, &expected));
+        assert!(mismatch(&Matcher::new(), &left, &expected));
+
+        // Fields set in expected must match.
+        let expected = Complex {
+            optional_enum: Some(ComplexEnum::Two),
+            ..Complex::default()
+        };
+        assert!(mismatch(&Matcher::new().contains(), &left, &expected));
+        let expected = complex(&["a"], &[(1, "x")]);
+        assert!(mismatch(&Matcher::new().contains(), &left, &expected));
+    }
+
+    #[test]
+    fn encodes_deterministically() {
+        let a = complex(&[], &[(3, "c"), (1, "a"), (2, "b")]);
+        let b = complex(&[], &[(2, "b"), (3, "c"), (1, "a")]);
+        assert_eq!(encode(&a), encode(&b));
+        assert_eq!(encode(&a), encode(&a.clone().reflect()));
+        assert_eq!(Complex::decode(&encode(&a)).unwrap(), a);
+    }
+
+    #[test]
+    fn rejects_paths_that_do_not_fit() {
+        let m = Complex::default();
+        let cases = [
+            (Matcher::new().ignore("nope"), ErrorKind::UnknownField),
+            (
+                Matcher::new().ignore("optional_enum.x"),
+                ErrorKind::InvalidPath,
+            ),
+            (
+                Matcher::new().unordered("optional_enum"),
+                ErrorKind::InvalidPath,
+            ),
+            (
+                Matcher::new().unordered("map_message"),
+                ErrorKind::InvalidPath,
+            ),
+        ];
+        for (matcher, kind) in cases {
+            assert_eq!(matcher.check(&m, &m).unwrap_err().kind(), kind);
+        }
+    }
+}
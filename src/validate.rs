@@ -0,0 +1,196 @@
+use std::fmt::Display;
+
+use crate::{
+    descriptor::{FieldDescriptor, Label, MessageDescriptor},
+    error::AbsorbError,
+    value::{Key, Map, Message, Rule, Value, ValueRef, Variant},
+};
+
+/// Checks a message against its descriptor: the number of fields, the type, rule and map key type
+/// of each value, closed enum values, and nested messages. Returns the first violation found.
+pub fn validate(m: &Message, d: &MessageDescriptor) -> Result<(), AbsorbError> {
+    let mut out = Violations::first();
+    visit_message(m, d, &mut out);
+    out.into_result()
+}
+
+/// Like validate, but reports every violation, in field order.
+pub fn validate_all(m: &Message, d: &MessageDescriptor) -> Result<(), AbsorbError> {
+    let mut out = Violations::all();
+    visit_message(m, d, &mut out);
+    out.into_result()
+}
+
+// Checks that a value can be stored in a field without breaking the invariants concrete types
+// rely on, including the contents of nested messages.
+pub(crate) fn check_value(v: &Value, f: &FieldDescriptor) -> Result<(), AbsorbError> {
+    let mut out = Violations::first();
+    visit_value(v, f, &mut out);
+    out.into_result()
+}
+
+// Like check_value, for a single element of a repeated or map field.
+pub(crate) fn check_element(v: &Value, f: &FieldDescriptor) -> Result<(), AbsorbError> {
+    if !f.matches_type(v) || !v.is_singular() {
+        return Err(AbsorbError::invalid_element(f, v));
+    }
+    let mut out = Violations::first();
+    visit_contents(v, f, &mut out);
+    out.into_result()
+}
+
+// Violations found while walking a message. Unless all of them are wanted, the walk stops at the
+// first one.
+struct Violations {
+    all: bool,
+    errors: Vec<AbsorbError>,
+}
+
+impl Violations {
+    fn first() -> Self {
+        Violations {
+            all: false,
+            errors: Vec::new(),
+        }
+    }
+
+    fn all() -> Self {
+        Violations {
+            all: true,
+            errors: Vec::new(),
+        }
+    }
+
+    fn done(&self) -> bool {
+        !self.all && !self.errors.is_empty()
+    }
+
+    fn into_result(self) -> Result<(), AbsorbError> {
+        AbsorbError::multiple(self.errors).map_or(Ok(()), Err)
+    }
+}
+
+fn visit_value(v: &Value, f: &FieldDescriptor, out: &mut Violations) {
+    if !f.matches(v) {
+        return out.errors.push(AbsorbError::invalid_type(f, v));
+    }
+    visit_contents(v, f, out)
+}
+
+fn visit_contents(v: &Value, f: &FieldDescriptor, out: &mut Violations) {
+    match v {
+        Value::Enum(rule) => match f.enum_descriptor() {
+            Some(d) if d.closed => {
+                for (key, e) in elements(rule) {
+                    if d.value_by_number(e.number).is_none() {
+                        out.errors
+                            .push(locate(AbsorbError::invalid_enum(d.name, e), f, key));
+                    }
+                    if out.done() {
+                        return;
+                    }
+                }
+            }
+            _ => {}
+        },
+        Value::Message(rule) => {
+            if let Some(d) = f.message_descriptor() {
+                for (key, m) in elements(rule) {
+                    let start = out.errors.len();
+                    visit_message(m, d, out);
+                    let nested = out.errors.split_off(start);
+                    out.errors
+                        .extend(nested.into_iter().map(|e| locate(e, f, key.clone())));
+                    if out.done() {
+                        return;
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn visit_message(m: &Message, d: &MessageDescriptor, out: &mut Violations) {
+    if m.fields.len() != d.fields.len() {
+        return out
+            .errors
+            .push(AbsorbError::invalid_length(d.fields.len(), m.fields.len()));
+    }
+
+    for (v, f) in m.fields.iter().zip(d.fields) {
+        match v {
+            Some(v) => visit_value(v, f, out),
+            None if f.label == Label::Repeated => {
+                out.errors.push(AbsorbError::not_optional(f.name))
+            }
+            None => {}
+        }
+        if out.done() {
+            return;
+        }
+    }
+}
+
+// Places an error found in an element of a field, given the element's index or key if the field
+// is repeated or a map.
+fn locate(e: AbsorbError, f: &FieldDescriptor, key: Option<String>) -> AbsorbError {
+    match key {
+        Some(key) => e.at_key(f.name, key),
+        None => e.at(f.name),
+    }
+}
+
+// The elements of a rule with their index or key, if any. Map entries are sorted by key.
+pub(crate) fn elements<T>(rule: &Rule<T>) -> Vec<(Option<String>, &T)> {
+    match rule {
+        Rule::Singular(v) => vec![(None, v)],
+        Rule::Repeated(v) => v
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (Some(i.to_string()), v))
+            .collect(),
+        Rule::Map(k) => match k {
+            Key::Bool(m) => entries(m),
+            Key::I32(m) => entries(m),
+            Key::I64(m) => entries(m),
+            Key::String(m) => entries(m),
+            Key::U32(m) => entries(m),
+            Key::U64(m) => entries(m),
+        },
+    }
+}
+
+fn entries<K: Ord + Display, T>(m: &Map<K, T>) -> Vec<(Option<String>, &T)> {
+    // Visit entries in key order so reports are stable.
+    let mut entries: Vec<_> = m.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    entries
+        .into_iter()
+        .map(|(k, v)| (Some(k.to_string()), v))
+        .collect()
+}
+
+// The elements of a value with their index or key, as in elements.
+pub(crate) fn element_refs(v: &Value) -> Vec<(Option<String>, ValueRef<'_>)> {
+    match v {
+        Value::Bool(rule) => refs(rule),
+        Value::Bytes(rule) => refs(rule),
+        Value::Enum(rule) => refs(rule),
+        Value::F32(rule) => refs(rule),
+        Value::F64(rule) => refs(rule),
+        Value::I32(rule) => refs(rule),
+        Value::I64(rule) => refs(rule),
+        Value::Message(rule) => refs(rule),
+        Value::String(rule) => refs(rule),
+        Value::U32(rule) => refs(rule),
+        Value::U64(rule) => refs(rule),
+    }
+}
+
+fn refs<T: Variant>(rule: &Rule<T>) -> Vec<(Option<String>, ValueRef<'_>)> {
+    elements(rule)
+        .into_iter()
+        .map(|(key, v)| (key, v.as_ref()))
+        .collect()
+}
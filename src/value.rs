@@ -1,6 +1,11 @@
-use std::collections::HashMap;
+use std::hash::Hash;
 
-#[derive(Debug, Clone)]
+use crate::{
+    descriptor::{FieldDescriptor, Label, MessageDescriptor, Type},
+    error::AbsorbError,
+};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Bool(Rule<bool>),
     Bytes(Rule<Vec<u8>>),
@@ -15,24 +20,127 @@ pub enum Value {
     U64(Rule<u64>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Rule<T> {
     Singular(T),
     Repeated(Vec<T>),
     Map(Key<T>),
 }
 
-#[derive(Debug, Clone)]
+/// The map behind map fields, and so behind the map fields of heavy and light types. A HashMap
+/// unless the `btree-map` feature selects a BTreeMap, sorted by key, or the `index-map` feature an
+/// IndexMap, in insertion order. `index-map` wins if both are enabled, so that features stay
+/// additive when two crates in a build each pick one.
+#[cfg(not(any(feature = "btree-map", feature = "index-map")))]
+pub type Map<K, V> = std::collections::HashMap<K, V>;
+/// The map behind map fields, sorted by key as selected by the `btree-map` feature.
+#[cfg(all(feature = "btree-map", not(feature = "index-map")))]
+pub type Map<K, V> = std::collections::BTreeMap<K, V>;
+/// The map behind map fields, in insertion order as selected by the `index-map` feature.
+#[cfg(feature = "index-map")]
+pub type Map<K, V> = indexmap::IndexMap<K, V>;
+
+// Removes an entry, keeping the order of the rest.
+#[cfg(not(feature = "index-map"))]
+pub(crate) fn remove_entry<K: Ord + Hash, V>(m: &mut Map<K, V>, k: &K) -> Option<V> {
+    m.remove(k)
+}
+#[cfg(feature = "index-map")]
+pub(crate) fn remove_entry<K: Ord + Hash, V>(m: &mut Map<K, V>, k: &K) -> Option<V> {
+    m.shift_remove(k)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Key<T> {
-    Bool(HashMap<bool, T>),
-    I32(HashMap<i32, T>),
-    I64(HashMap<i64, T>),
-    String(HashMap<String, T>),
-    U32(HashMap<u32, T>),
-    U64(HashMap<u64, T>),
+    Bool(Map<bool, T>),
+    I32(Map<i32, T>),
+    I64(Map<i64, T>),
+    String(Map<String, T>),
+    U32(Map<u32, T>),
+    U64(Map<u64, T>),
 }
 
 impl Value {
+    // The empty list or map of a repeated field. None for a singular field, and for a map keyed by
+    // a type that cannot key a map, which only a hand-built descriptor can name.
+    pub fn empty(field: &FieldDescriptor) -> Option<Value> {
+        if field.label != Label::Repeated {
+            return None;
+        }
+
+        Some(match field.ty {
+            Type::Bool => Value::Bool(Rule::empty(field.key)?),
+            Type::Bytes => Value::Bytes(Rule::empty(field.key)?),
+            Type::Enum => Value::Enum(Rule::empty(field.key)?),
+            Type::Float => Value::F32(Rule::empty(field.key)?),
+            Type::Double => Value::F64(Rule::empty(field.key)?),
+            Type::Int32 | Type::SInt32 | Type::SFixed32 => Value::I32(Rule::empty(field.key)?),
+            Type::Int64 | Type::SInt64 | Type::SFixed64 => Value::I64(Rule::empty(field.key)?),
+            Type::Message | Type::Group => Value::Message(Rule::empty(field.key)?),
+            Type::String => Value::String(Rule::empty(field.key)?),
+            Type::UInt32 | Type::Fixed32 => Value::U32(Rule::empty(field.key)?),
+            Type::UInt64 | Type::Fixed64 => Value::U64(Rule::empty(field.key)?),
+        })
+    }
+
+    // The value of a single element of the field that has not been set, or None when a message
+    // field has no descriptor.
+    pub fn default_for(field: &FieldDescriptor) -> Option<Value> {
+        Some(match field.ty {
+            Type::Bool => Value::Bool(Rule::Singular(false)),
+            Type::Bytes => Value::Bytes(Rule::Singular(Vec::new())),
+            Type::Enum => Value::Enum(Rule::Singular(Enum {
+                number: field
+                    .enum_descriptor()
+                    .and_then(|d| d.values.first())
+                    .map_or(0, |v| v.number),
+            })),
+            Type::Float => Value::F32(Rule::Singular(0.0)),
+            Type::Double => Value::F64(Rule::Singular(0.0)),
+            Type::Int32 | Type::SInt32 | Type::SFixed32 => Value::I32(Rule::Singular(0)),
+            Type::Int64 | Type::SInt64 | Type::SFixed64 => Value::I64(Rule::Singular(0)),
+            Type::Message | Type::Group => {
+                Value::Message(Rule::Singular(Message::new(field.message_descriptor()?)))
+            }
+            Type::String => Value::String(Rule::Singular(String::new())),
+            Type::UInt32 | Type::Fixed32 => Value::U32(Rule::Singular(0)),
+            Type::UInt64 | Type::Fixed64 => Value::U64(Rule::Singular(0)),
+        })
+    }
+
+    pub fn is_singular(&self) -> bool {
+        match self {
+            Value::Bool(v) => v.is_singular(),
+            Value::Bytes(v) => v.is_singular(),
+            Value::Enum(v) => v.is_singular(),
+            Value::F32(v) => v.is_singular(),
+            Value::F64(v) => v.is_singular(),
+            Value::I32(v) => v.is_singular(),
+            Value::I64(v) => v.is_singular(),
+            Value::Message(v) => v.is_singular(),
+            Value::String(v) => v.is_singular(),
+            Value::U32(v) => v.is_singular(),
+            Value::U64(v) => v.is_singular(),
+        }
+    }
+
+    // Whether the value is indistinguishable from an unset field without presence.
+    pub fn is_default(&self) -> bool {
+        match self {
+            Value::Bool(v) => v.is_default(|v| !*v),
+            Value::Bytes(v) => v.is_default(Vec::is_empty),
+            Value::Enum(v) => v.is_default(|v| v.number == 0),
+            Value::F32(v) => v.is_default(|v| v.to_bits() == 0),
+            Value::F64(v) => v.is_default(|v| v.to_bits() == 0),
+            Value::I32(v) => v.is_default(|v| *v == 0),
+            Value::I64(v) => v.is_default(|v| *v == 0),
+            Value::Message(v) => v.is_default(|_| false),
+            Value::String(v) => v.is_default(String::is_empty),
+            Value::U32(v) => v.is_default(|v| *v == 0),
+            Value::U64(v) => v.is_default(|v| *v == 0),
+        }
+    }
+
     pub fn type_string(&self) -> String {
         match self {
             Value::Bool(v) => format!("Value::Bool({})", v.type_string()),
@@ -51,6 +159,41 @@ impl Value {
 }
 
 impl<T> Rule<T> {
+    fn empty(key: Option<Type>) -> Option<Self> {
+        match key {
+            Some(key) => Key::empty(key).map(Rule::Map),
+            None => Some(Rule::Repeated(Vec::new())),
+        }
+    }
+
+    pub fn is_singular(&self) -> bool {
+        matches!(self, Rule::Singular(_))
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        match self {
+            Rule::Singular(v) => Box::new(std::iter::once(v)),
+            Rule::Repeated(v) => Box::new(v.iter()),
+            Rule::Map(k) => k.values(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        match self {
+            Rule::Singular(v) => Box::new(std::iter::once(v)),
+            Rule::Repeated(v) => Box::new(v.iter_mut()),
+            Rule::Map(k) => k.values_mut(),
+        }
+    }
+
+    fn is_default(&self, singular: impl Fn(&T) -> bool) -> bool {
+        match self {
+            Rule::Singular(v) => singular(v),
+            Rule::Repeated(v) => v.is_empty(),
+            Rule::Map(k) => k.is_empty(),
+        }
+    }
+
     pub fn type_string(&self) -> String {
         match self {
             Rule::Singular(_) => "Rule::Singular()".to_owned(),
@@ -60,7 +203,87 @@ impl<T> Rule<T> {
     }
 }
 
+impl<T: Clone> Rule<T> {
+    fn merge_from(&mut self, other: &Rule<T>) {
+        match (self, other) {
+            (Rule::Singular(dst), Rule::Singular(src)) => *dst = src.clone(),
+            (Rule::Repeated(dst), Rule::Repeated(src)) => dst.extend_from_slice(src),
+            (Rule::Map(dst), Rule::Map(src)) => dst.merge_from(src),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<T: Clone> Key<T> {
+    fn merge_from(&mut self, other: &Key<T>) {
+        match (self, other) {
+            (Key::Bool(dst), Key::Bool(src)) => {
+                dst.extend(src.iter().map(|(k, v)| (*k, v.clone())))
+            }
+            (Key::I32(dst), Key::I32(src)) => dst.extend(src.iter().map(|(k, v)| (*k, v.clone()))),
+            (Key::I64(dst), Key::I64(src)) => dst.extend(src.iter().map(|(k, v)| (*k, v.clone()))),
+            (Key::String(dst), Key::String(src)) => {
+                dst.extend(src.iter().map(|(k, v)| (k.clone(), v.clone())))
+            }
+            (Key::U32(dst), Key::U32(src)) => dst.extend(src.iter().map(|(k, v)| (*k, v.clone()))),
+            (Key::U64(dst), Key::U64(src)) => dst.extend(src.iter().map(|(k, v)| (*k, v.clone()))),
+            _ => unreachable!(),
+        }
+    }
+}
+
 impl<T> Key<T> {
+    fn empty(key: Type) -> Option<Self> {
+        Some(match key {
+            Type::Bool => Key::Bool(Map::new()),
+            Type::Int32 | Type::SInt32 | Type::SFixed32 => Key::I32(Map::new()),
+            Type::Int64 | Type::SInt64 | Type::SFixed64 => Key::I64(Map::new()),
+            Type::String => Key::String(Map::new()),
+            Type::UInt32 | Type::Fixed32 => Key::U32(Map::new()),
+            Type::UInt64 | Type::Fixed64 => Key::U64(Map::new()),
+            Type::Bytes | Type::Double | Type::Enum | Type::Float | Type::Group | Type::Message => {
+                return None
+            }
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Key::Bool(v) => v.len(),
+            Key::I32(v) => v.len(),
+            Key::I64(v) => v.len(),
+            Key::String(v) => v.len(),
+            Key::U32(v) => v.len(),
+            Key::U64(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn values(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        match self {
+            Key::Bool(v) => Box::new(v.values()),
+            Key::I32(v) => Box::new(v.values()),
+            Key::I64(v) => Box::new(v.values()),
+            Key::String(v) => Box::new(v.values()),
+            Key::U32(v) => Box::new(v.values()),
+            Key::U64(v) => Box::new(v.values()),
+        }
+    }
+
+    pub fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        match self {
+            Key::Bool(v) => Box::new(v.values_mut()),
+            Key::I32(v) => Box::new(v.values_mut()),
+            Key::I64(v) => Box::new(v.values_mut()),
+            Key::String(v) => Box::new(v.values_mut()),
+            Key::U32(v) => Box::new(v.values_mut()),
+            Key::U64(v) => Box::new(v.values_mut()),
+        }
+    }
+
     pub fn type_string(&self) -> String {
         match self {
             Key::Bool(_) => "Key::Bool".to_owned(),
@@ -73,13 +296,538 @@ impl<T> Key<T> {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct Enum {
     pub number: i32,
 }
 
-#[derive(Debug, Clone)]
+/// The fields of a message in descriptor order, with the bytes of fields it does not know.
+///
+/// The derived PartialEq is structural: an unset implicit field differs from one set to its
+/// default, map entries compare by the map type, and NaN is unequal to itself. Use eq::equal for
+/// protobuf semantics.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Message {
     pub fields: Vec<Option<Value>>,
+    pub unknown_fields: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(descriptor: &MessageDescriptor) -> Self {
+        Message {
+            fields: descriptor.fields.iter().map(Value::empty).collect(),
+            unknown_fields: Vec::new(),
+        }
+    }
+
+    /// Merges other into this message the way protobuf does: singular fields that are set in other
+    /// are overwritten, except for messages which merge recursively, repeated fields append, and
+    /// map entries are replaced by key. Implicit fields holding their default count as unset.
+    pub fn merge_from(
+        &mut self,
+        other: &Message,
+        d: &MessageDescriptor,
+    ) -> Result<(), AbsorbError> {
+        for m in [&*self, other] {
+            if m.fields.len() != d.fields.len() {
+                return Err(AbsorbError::invalid_length(d.fields.len(), m.fields.len()));
+            }
+        }
+
+        for ((dst, src), f) in self.fields.iter_mut().zip(&other.fields).zip(d.fields) {
+            merge_field(dst, src, f)?;
+        }
+        self.unknown_fields.extend_from_slice(&other.unknown_fields);
+        Ok(())
+    }
+}
+
+// Merges a single field of Message::merge_from.
+pub(crate) fn merge_field(
+    dst: &mut Option<Value>,
+    src: &Option<Value>,
+    f: &FieldDescriptor,
+) -> Result<(), AbsorbError> {
+    let src = match src {
+        Some(src) if !f.matches(src) => return Err(AbsorbError::invalid_type(f, src)),
+        Some(src) if !f.has_presence() && src.is_singular() && src.is_default() => return Ok(()),
+        Some(src) => src,
+        None => return Ok(()),
+    };
+    match dst {
+        Some(dst) if !f.matches(dst) => Err(AbsorbError::invalid_type(f, dst)),
+        Some(dst) => merge_value(dst, src, f).map_err(|e| e.at(f.name)),
+        None => {
+            *dst = Some(src.clone());
+            Ok(())
+        }
+    }
+}
+
+// Both values have been checked against the field, so they are the same variant.
+fn merge_value(dst: &mut Value, src: &Value, f: &FieldDescriptor) -> Result<(), AbsorbError> {
+    match (dst, src) {
+        (Value::Message(Rule::Singular(dst)), Value::Message(Rule::Singular(src))) => {
+            let d = f
+                .message_descriptor()
+                .ok_or_else(|| AbsorbError::missing_descriptor(f.name))?;
+            return dst.merge_from(src, d);
+        }
+        (Value::Bool(dst), Value::Bool(src)) => dst.merge_from(src),
+        (Value::Bytes(dst), Value::Bytes(src)) => dst.merge_from(src),
+        (Value::Enum(dst), Value::Enum(src)) => dst.merge_from(src),
+        (Value::F32(dst), Value::F32(src)) => dst.merge_from(src),
+        (Value::F64(dst), Value::F64(src)) => dst.merge_from(src),
+        (Value::I32(dst), Value::I32(src)) => dst.merge_from(src),
+        (Value::I64(dst), Value::I64(src)) => dst.merge_from(src),
+        (Value::Message(dst), Value::Message(src)) => dst.merge_from(src),
+        (Value::String(dst), Value::String(src)) => dst.merge_from(src),
+        (Value::U32(dst), Value::U32(src)) => dst.merge_from(src),
+        (Value::U64(dst), Value::U64(src)) => dst.merge_from(src),
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+// A borrowed element of a field, as handed out by lists and maps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueRef<'a> {
+    Bool(bool),
+    Bytes(&'a [u8]),
+    Enum(Enum),
+    F32(f32),
+    F64(f64),
+    I32(i32),
+    I64(i64),
+    Message(&'a Message),
+    String(&'a str),
+    U32(u32),
+    U64(u64),
+}
+
+impl<'a> ValueRef<'a> {
+    // Copies the element into a singular Value.
+    pub fn to_value(self) -> Value {
+        match self {
+            ValueRef::Bool(v) => Value::Bool(Rule::Singular(v)),
+            ValueRef::Bytes(v) => Value::Bytes(Rule::Singular(v.to_vec())),
+            ValueRef::Enum(v) => Value::Enum(Rule::Singular(v)),
+            ValueRef::F32(v) => Value::F32(Rule::Singular(v)),
+            ValueRef::F64(v) => Value::F64(Rule::Singular(v)),
+            ValueRef::I32(v) => Value::I32(Rule::Singular(v)),
+            ValueRef::I64(v) => Value::I64(Rule::Singular(v)),
+            ValueRef::Message(v) => Value::Message(Rule::Singular(v.clone())),
+            ValueRef::String(v) => Value::String(Rule::Singular(v.to_string())),
+            ValueRef::U32(v) => Value::U32(Rule::Singular(v)),
+            ValueRef::U64(v) => Value::U64(Rule::Singular(v)),
+        }
+    }
+}
+
+// A type that can be stored in a Value.
+pub(crate) trait Variant: Sized {
+    fn into_value(rule: Rule<Self>) -> Value;
+    fn from_value(value: Value) -> Option<Rule<Self>>;
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>>;
+    fn as_ref(&self) -> ValueRef<'_>;
+
+    // Unwraps a singular value that has already been checked against the field.
+    fn from_element(value: Value) -> Self {
+        match Self::from_value(value) {
+            Some(Rule::Singular(v)) => v,
+            _ => unreachable!(),
+        }
+    }
+}
+
+// A type that can be stored as the key of a map.
+pub(crate) trait KeyVariant: Variant + Ord + Hash + Clone {
+    fn into_key<T>(m: Map<Self, T>) -> Key<T>;
+    fn map_mut<T>(key: &mut Key<T>) -> Option<&mut Map<Self, T>>;
+    fn from_ref(v: ValueRef) -> Option<Self>;
+}
+
+impl Variant for bool {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::Bool(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::Bool(*self)
+    }
+}
+
+impl Variant for Vec<u8> {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::Bytes(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::Bytes(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::Bytes(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::Bytes(self)
+    }
+}
+
+impl Variant for Enum {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::Enum(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::Enum(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::Enum(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::Enum(*self)
+    }
+}
+
+impl Variant for f32 {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::F32(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::F32(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::F32(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::F32(*self)
+    }
+}
+
+impl Variant for f64 {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::F64(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::F64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::F64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::F64(*self)
+    }
+}
+
+impl Variant for i32 {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::I32(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::I32(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::I32(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::I32(*self)
+    }
+}
+
+impl Variant for i64 {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::I64(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::I64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::I64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::I64(*self)
+    }
+}
+
+impl Variant for Message {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::Message(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::Message(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::Message(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::Message(self)
+    }
+}
+
+impl Variant for String {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::String(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::String(self)
+    }
+}
+
+impl Variant for u32 {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::U32(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::U32(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::U32(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::U32(*self)
+    }
+}
+
+impl Variant for u64 {
+    fn into_value(rule: Rule<Self>) -> Value {
+        Value::U64(rule)
+    }
+
+    fn from_value(value: Value) -> Option<Rule<Self>> {
+        match value {
+            Value::U64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn rule_mut(value: &mut Value) -> Option<&mut Rule<Self>> {
+        match value {
+            Value::U64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_ref(&self) -> ValueRef<'_> {
+        ValueRef::U64(*self)
+    }
+}
+
+impl KeyVariant for bool {
+    fn into_key<T>(m: Map<Self, T>) -> Key<T> {
+        Key::Bool(m)
+    }
+
+    fn map_mut<T>(key: &mut Key<T>) -> Option<&mut Map<Self, T>> {
+        match key {
+            Key::Bool(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    fn from_ref(v: ValueRef) -> Option<Self> {
+        match v {
+            ValueRef::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl KeyVariant for i32 {
+    fn into_key<T>(m: Map<Self, T>) -> Key<T> {
+        Key::I32(m)
+    }
+
+    fn map_mut<T>(key: &mut Key<T>) -> Option<&mut Map<Self, T>> {
+        match key {
+            Key::I32(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    fn from_ref(v: ValueRef) -> Option<Self> {
+        match v {
+            ValueRef::I32(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl KeyVariant for i64 {
+    fn into_key<T>(m: Map<Self, T>) -> Key<T> {
+        Key::I64(m)
+    }
+
+    fn map_mut<T>(key: &mut Key<T>) -> Option<&mut Map<Self, T>> {
+        match key {
+            Key::I64(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    fn from_ref(v: ValueRef) -> Option<Self> {
+        match v {
+            ValueRef::I64(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl KeyVariant for String {
+    fn into_key<T>(m: Map<Self, T>) -> Key<T> {
+        Key::String(m)
+    }
+
+    fn map_mut<T>(key: &mut Key<T>) -> Option<&mut Map<Self, T>> {
+        match key {
+            Key::String(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    fn from_ref(v: ValueRef) -> Option<Self> {
+        match v {
+            ValueRef::String(v) => Some(v.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl KeyVariant for u32 {
+    fn into_key<T>(m: Map<Self, T>) -> Key<T> {
+        Key::U32(m)
+    }
+
+    fn map_mut<T>(key: &mut Key<T>) -> Option<&mut Map<Self, T>> {
+        match key {
+            Key::U32(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    fn from_ref(v: ValueRef) -> Option<Self> {
+        match v {
+            ValueRef::U32(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl KeyVariant for u64 {
+    fn into_key<T>(m: Map<Self, T>) -> Key<T> {
+        Key::U64(m)
+    }
+
+    fn map_mut<T>(key: &mut Key<T>) -> Option<&mut Map<Self, T>> {
+        match key {
+            Key::U64(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    fn from_ref(v: ValueRef) -> Option<Self> {
+        match v {
+            ValueRef::U64(v) => Some(v),
+            _ => None,
+        }
+    }
 }
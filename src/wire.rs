@@ -0,0 +1,343 @@
+use std::convert::TryFrom;
+
+use crate::{codec::RECURSION_LIMIT, error::DecodeError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireType {
+    Varint = 0,
+    Fixed64 = 1,
+    Len = 2,
+    StartGroup = 3,
+    EndGroup = 4,
+    Fixed32 = 5,
+}
+
+impl TryFrom<u64> for WireType {
+    type Error = DecodeError;
+
+    fn try_from(v: u64) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(WireType::Varint),
+            1 => Ok(WireType::Fixed64),
+            2 => Ok(WireType::Len),
+            3 => Ok(WireType::StartGroup),
+            4 => Ok(WireType::EndGroup),
+            5 => Ok(WireType::Fixed32),
+            v => Err(DecodeError::InvalidWireType(v)),
+        }
+    }
+}
+
+pub fn encode_varint(mut v: u64, buf: &mut Vec<u8>) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+pub fn varint_len(v: u64) -> usize {
+    // Each byte holds 7 bits; zero still takes one byte.
+    (64 - (v | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+pub fn encode_tag(number: u32, wire_type: WireType, buf: &mut Vec<u8>) {
+    encode_varint((u64::from(number) << 3) | wire_type as u64, buf);
+}
+
+pub fn tag_len(number: u32) -> usize {
+    varint_len(u64::from(number) << 3)
+}
+
+pub fn encode_zigzag32(v: i32) -> u64 {
+    u64::from(((v << 1) ^ (v >> 31)) as u32)
+}
+
+pub fn encode_zigzag64(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub fn decode_zigzag32(v: u64) -> i32 {
+    let v = v as u32;
+    ((v >> 1) as i32) ^ -((v & 1) as i32)
+}
+
+pub fn decode_zigzag64(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut v = 0u64;
+        for (i, &b) in self.buf.iter().enumerate().take(10) {
+            if i == 9 && b > 1 {
+                return Err(DecodeError::InvalidVarint);
+            }
+            v |= u64::from(b & 0x7f) << (7 * i);
+            if b < 0x80 {
+                self.buf = &self.buf[i + 1..];
+                return Ok(v);
+            }
+        }
+
+        if self.buf.len() < 10 {
+            Err(DecodeError::Truncated)
+        } else {
+            Err(DecodeError::InvalidVarint)
+        }
+    }
+
+    pub fn read_fixed32(&mut self) -> Result<u32, DecodeError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_slice(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_fixed64(&mut self) -> Result<u64, DecodeError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_slice(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.read_varint()?;
+        if len > self.buf.len() as u64 {
+            return Err(DecodeError::Truncated);
+        }
+        self.read_slice(len as usize)
+    }
+
+    pub fn read_tag(&mut self) -> Result<(u32, WireType), DecodeError> {
+        let tag = self.read_varint()?;
+        let number = tag >> 3;
+        if number == 0 || number > u64::from(u32::MAX >> 3) {
+            return Err(DecodeError::InvalidFieldNumber(number));
+        }
+        Ok((number as u32, WireType::try_from(tag & 0x7)?))
+    }
+
+    // Skips a field of a message nested depth levels deep. Groups count as a level, so skipping
+    // nested groups stops at RECURSION_LIMIT like decoding nested messages does.
+    pub fn skip(
+        &mut self,
+        number: u32,
+        wire_type: WireType,
+        depth: usize,
+    ) -> Result<(), DecodeError> {
+        match wire_type {
+            WireType::Varint => self.read_varint().map(drop),
+            WireType::Fixed64 => self.read_slice(8).map(drop),
+            WireType::Len => self.read_bytes().map(drop),
+            WireType::Fixed32 => self.read_slice(4).map(drop),
+            WireType::StartGroup => self.skip_group(number, depth + 1),
+            WireType::EndGroup => Err(DecodeError::UnexpectedEndGroup(number)),
+        }
+    }
+
+    pub fn skip_group(&mut self, number: u32, depth: usize) -> Result<(), DecodeError> {
+        if depth > RECURSION_LIMIT {
+            return Err(DecodeError::RecursionLimit(RECURSION_LIMIT));
+        }
+        loop {
+            if self.is_empty() {
+                return Err(DecodeError::Truncated);
+            }
+            match self.read_tag()? {
+                (n, WireType::EndGroup) if n == number => return Ok(()),
+                (n, wire_type) => self.skip(n, wire_type, depth)?,
+            }
+        }
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.buf.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(v: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_varint(v, &mut buf);
+        buf
+    }
+
+    #[test]
+    fn varints() {
+        let cases: &[(u64, &[u8])] = &[
+            (0, &[0x00]),
+            (1, &[0x01]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (150, &[0x96, 0x01]),
+            (300, &[0xac, 0x02]),
+            (
+                u64::MAX,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+            ),
+        ];
+        for &(v, bytes) in cases {
+            assert_eq!(varint(v), bytes, "{}", v);
+            assert_eq!(varint_len(v), bytes.len(), "{}", v);
+
+            let mut r = Reader::new(bytes);
+            assert_eq!(r.read_varint().unwrap(), v);
+            assert!(r.is_empty());
+        }
+    }
+
+    #[test]
+    fn overlong_varint() {
+        // The tenth byte may only carry the top bit of a u64.
+        let mut bytes = vec![0xff; 9];
+        bytes.push(0x02);
+        assert!(matches!(
+            Reader::new(&bytes).read_varint(),
+            Err(DecodeError::InvalidVarint)
+        ));
+
+        let bytes = [0x80; 11];
+        assert!(matches!(
+            Reader::new(&bytes).read_varint(),
+            Err(DecodeError::InvalidVarint)
+        ));
+    }
+
+    #[test]
+    fn truncated_varint() {
+        for bytes in [&[][..], &[0x80], &[0xff, 0xff]] {
+            assert!(matches!(
+                Reader::new(bytes).read_varint(),
+                Err(DecodeError::Truncated)
+            ));
+        }
+    }
+
+    #[test]
+    fn zigzag() {
+        let cases32 = [
+            (0, 0),
+            (-1, 1),
+            (1, 2),
+            (-2, 3),
+            (i32::MAX, 0xffff_fffe),
+            (i32::MIN, 0xffff_ffff),
+        ];
+        for (v, encoded) in cases32 {
+            assert_eq!(encode_zigzag32(v), encoded);
+            assert_eq!(decode_zigzag32(encoded), v);
+        }
+
+        let cases64 = [
+            (0, 0),
+            (-1, 1),
+            (1, 2),
+            (i64::MAX, u64::MAX - 1),
+            (i64::MIN, u64::MAX),
+        ];
+        for (v, encoded) in cases64 {
+            assert_eq!(encode_zigzag64(v), encoded);
+            assert_eq!(decode_zigzag64(encoded), v);
+        }
+    }
+
+    #[test]
+    fn tags() {
+        let mut buf = Vec::new();
+        encode_tag(1, WireType::Varint, &mut buf);
+        encode_tag(16, WireType::Len, &mut buf);
+        assert_eq!(buf, [0x08, 0x82, 0x01]);
+        assert_eq!(tag_len(1), 1);
+        assert_eq!(tag_len(16), 2);
+
+        let mut r = Reader::new(&buf);
+        assert_eq!(r.read_tag().unwrap(), (1, WireType::Varint));
+        assert_eq!(r.read_tag().unwrap(), (16, WireType::Len));
+    }
+
+    #[test]
+    fn bad_tags() {
+        assert!(matches!(
+            Reader::new(&[0x0f]).read_tag(),
+            Err(DecodeError::InvalidWireType(7))
+        ));
+        assert!(matches!(
+            Reader::new(&[0x00]).read_tag(),
+            Err(DecodeError::InvalidFieldNumber(0))
+        ));
+    }
+
+    #[test]
+    fn fixed_and_bytes() {
+        let mut r = Reader::new(&[0x01, 0x00, 0x00, 0x00, 0x02, 0x61, 0x62, 0x05]);
+        assert_eq!(r.read_fixed32().unwrap(), 1);
+        assert_eq!(r.read_bytes().unwrap(), b"ab");
+        // A length running past the end of the input.
+        assert!(matches!(r.read_bytes(), Err(DecodeError::Truncated)));
+        assert!(matches!(
+            Reader::new(&[0; 7]).read_fixed64(),
+            Err(DecodeError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn skips_groups() {
+        // Group 1 holding field 2 = 1, then field 3 = 2.
+        let mut r = Reader::new(&[0x0b, 0x10, 0x01, 0x0c, 0x18, 0x02]);
+        let (number, wire_type) = r.read_tag().unwrap();
+        r.skip(number, wire_type, 0).unwrap();
+        assert_eq!(r.remaining(), [0x18, 0x02]);
+
+        let mut r = Reader::new(&[0x0b, 0x10, 0x01]);
+        let (number, wire_type) = r.read_tag().unwrap();
+        assert!(matches!(
+            r.skip(number, wire_type, 0),
+            Err(DecodeError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn limits_group_nesting() {
+        // Groups of field 100 nested far deeper than the stack could recurse.
+        let bytes = [0xa3, 0x06].repeat(1_000_000);
+        let mut r = Reader::new(&bytes);
+        let (number, wire_type) = r.read_tag().unwrap();
+        assert!(matches!(
+            r.skip(number, wire_type, 0),
+            Err(DecodeError::RecursionLimit(_))
+        ));
+
+        let bytes = [
+            [0x0b].repeat(RECURSION_LIMIT),
+            [0x0c].repeat(RECURSION_LIMIT),
+        ]
+        .concat();
+        let mut r = Reader::new(&bytes);
+        let (number, wire_type) = r.read_tag().unwrap();
+        r.skip(number, wire_type, 0).unwrap();
+        assert!(r.is_empty());
+    }
+}
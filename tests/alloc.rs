@@ -0,0 +1,54 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use tobu_conversion::{
+    codec,
+    heavy::complex::{Complex, ComplexNested},
+    message::ProtoMessage,
+    value::Message,
+};
+
+// Counts allocations, so this file holds a single test to keep others from adding to the count.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+fn encoded_len_does_not_allocate() {
+    let mut m = Complex {
+        repeated_bytes: vec![b"a".to_vec(), b"b".to_vec()],
+        ..Complex::default()
+    };
+    for k in 0..16 {
+        let nested = ComplexNested {
+            optional_string: Some(k.to_string()),
+            ..ComplexNested::default()
+        };
+        m.map_message.insert(k, nested);
+    }
+    let m: Message = m.into();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let len = codec::encoded_len(&m, Complex::descriptor()).unwrap();
+    assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), before);
+
+    let mut buf = Vec::new();
+    codec::encode(&m, Complex::descriptor(), &mut buf).unwrap();
+    assert_eq!(buf.len(), len);
+}
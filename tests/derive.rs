@@ -0,0 +1,318 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+};
+
+use tobu_conversion::{
+    descriptor::{EnumDescriptor, EnumValueDescriptor, Label, Presence, Type},
+    enumeration::{EnumOrUnknown, ProtoEnum},
+    error::AbsorbError,
+    heavy::complex::ComplexEnum,
+    message::ProtoMessage,
+    reflect::{Reflect, ReflectBorrow, Reflection},
+    value::{Enum, Message, Rule, Value},
+    Reflect,
+};
+
+// Backs a map field with a type whose name does not end in "Map".
+type Table<K, V> = BTreeMap<K, V>;
+
+#[derive(Debug, Clone, Default, PartialEq, Reflect)]
+#[proto(name = "test.Item")]
+struct Item {
+    #[proto(number = 1, ty = "string", pattern = "^[a-z]+$")]
+    name: String,
+    #[proto(number = 2, ty = "sint32")]
+    delta: i32,
+    #[proto(number = 3, ty = "int32")]
+    ids: Vec<i32>,
+    #[proto(number = 4, ty = "enum")]
+    kind: Option<ComplexEnum>,
+    #[proto(number = 5, ty = "message")]
+    child: Option<Child>,
+    #[proto(number = 6, ty = "message", key = "string")]
+    children: HashMap<String, Child>,
+    #[proto(number = 7, ty = "bytes", key = "uint32")]
+    blobs: Table<u32, Vec<u8>>,
+    #[proto(number = 8, ty = "enum")]
+    color: EnumOrUnknown<Color>,
+    #[proto(unknown_fields)]
+    unknown_fields: Vec<u8>,
+}
+
+// An open enum, like every proto3 enum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Color {
+    Red = 0,
+    Green = 1,
+}
+
+impl From<Color> for Enum {
+    fn from(c: Color) -> Self {
+        Enum { number: c as i32 }
+    }
+}
+
+impl TryFrom<Enum> for Color {
+    type Error = AbsorbError;
+
+    fn try_from(e: Enum) -> Result<Self, Self::Error> {
+        Color::from_i32(e.number).ok_or_else(|| AbsorbError::invalid_enum("Color", &e))
+    }
+}
+
+impl ProtoEnum for Color {
+    fn descriptor() -> &'static EnumDescriptor {
+        static DESCRIPTOR: EnumDescriptor = EnumDescriptor {
+            name: "test.Color",
+            closed: false,
+            values: &[
+                EnumValueDescriptor {
+                    name: "RED",
+                    number: 0,
+                },
+                EnumValueDescriptor {
+                    name: "GREEN",
+                    number: 1,
+                },
+            ],
+        };
+        &DESCRIPTOR
+    }
+
+    fn from_i32(number: i32) -> Option<Self> {
+        match number {
+            0 => Some(Color::Red),
+            1 => Some(Color::Green),
+            _ => None,
+        }
+    }
+
+    fn values() -> &'static [Self] {
+        &[Color::Red, Color::Green]
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Reflect)]
+struct Child {
+    #[proto(number = 1, ty = "bool")]
+    flag: bool,
+}
+
+fn item() -> Item {
+    Item {
+        name: "widget".to_owned(),
+        delta: -2,
+        ids: vec![1, 150],
+        kind: Some(ComplexEnum::Ten),
+        child: Some(Child { flag: true }),
+        children: vec![("a".to_owned(), Child { flag: true })]
+            .into_iter()
+            .collect(),
+        blobs: vec![(7, vec![0xff])].into_iter().collect(),
+        color: Color::Green.into(),
+        unknown_fields: Vec::new(),
+    }
+}
+
+#[test]
+fn descriptor() {
+    let d = Item::descriptor();
+    assert_eq!(d.name, "test.Item");
+    let names: Vec<_> = d.fields.iter().map(|f| (f.name, f.number)).collect();
+    assert_eq!(
+        names,
+        [
+            ("name", 1),
+            ("delta", 2),
+            ("ids", 3),
+            ("kind", 4),
+            ("child", 5),
+            ("children", 6),
+            ("blobs", 7),
+            ("color", 8)
+        ]
+    );
+
+    assert_eq!(d.fields[1].ty, Type::SInt32);
+    assert_eq!(d.fields[1].presence, Presence::Implicit);
+    assert_eq!(d.fields[2].label, Label::Repeated);
+    assert_eq!(d.fields[3].presence, Presence::Explicit);
+    assert_eq!(d.fields[4].presence, Presence::Explicit);
+    assert_eq!(d.fields[5].key, Some(Type::String));
+    assert_eq!(d.fields[6].key, Some(Type::UInt32));
+    assert!(d.fields[4].message_descriptor().is_some());
+}
+
+#[test]
+fn reflection_round_trip() {
+    let r = item().reflect();
+    assert_eq!(
+        r.get("name"),
+        Some(&Value::String(Rule::Singular("widget".to_owned())))
+    );
+    assert_eq!(r.get(2), Some(&Value::I32(Rule::Singular(-2))));
+    assert_eq!(r.absorb().unwrap(), item());
+}
+
+#[test]
+fn reflect_mut_writes_back() {
+    let mut m = item();
+    m.reflect_mut()
+        .set("delta", Value::I32(Rule::Singular(5)))
+        .unwrap();
+    assert_eq!(m.delta, 5);
+    assert_eq!(m.reflect_ref().get("ids"), item().reflect_ref().get("ids"));
+}
+
+#[test]
+fn codec_round_trip() {
+    let m = item();
+    let mut buf = Vec::new();
+    m.encode(&mut buf);
+    assert_eq!(buf.len(), m.encoded_len());
+    assert_eq!(Item::decode(&buf).unwrap(), m);
+}
+
+#[test]
+fn encodes_scalars_like_protoc() {
+    let m = Item {
+        name: "a".to_owned(),
+        delta: -2,
+        ids: vec![1, 150],
+        ..Item::default()
+    };
+    let mut buf = Vec::new();
+    m.encode(&mut buf);
+    assert_eq!(
+        buf,
+        [0x0a, 0x01, b'a', 0x10, 0x03, 0x1a, 0x03, 0x01, 0x96, 0x01]
+    );
+}
+
+#[test]
+fn keeps_unknown_fields() {
+    // Field 15, varint 1.
+    let buf = [0x78, 0x01];
+    let m = Item::decode(&buf).unwrap();
+    assert_eq!(m.unknown_fields, buf);
+
+    let mut out = Vec::new();
+    m.encode(&mut out);
+    assert_eq!(out, buf);
+}
+
+#[test]
+fn unset_collections_absorb_as_empty() {
+    let m = Message {
+        fields: vec![None; 8],
+        unknown_fields: Vec::new(),
+    };
+    let item = Item::try_from(m).unwrap();
+    assert!(item.ids.is_empty());
+    assert!(item.children.is_empty());
+    assert!(item.blobs.is_empty());
+}
+
+#[test]
+fn checks_pattern() {
+    assert!(item().check_constraints().is_ok());
+
+    let m = Item {
+        name: "Widget".to_owned(),
+        ..item()
+    };
+    let e = Reflection::new(m).check_constraints().unwrap_err();
+    assert_eq!(e.violations().len(), 1);
+    assert_eq!(e.violations()[0].path, "name");
+}
+
+#[test]
+fn keeps_unknown_open_enum_values() {
+    // color = 5, which Color does not define.
+    let buf = [0x40, 0x05];
+    let m = Item::decode(&buf).unwrap();
+    assert_eq!(m.color.enum_value(), Err(5));
+    assert!(m.unknown_fields.is_empty());
+
+    let mut r = m.reflect();
+    assert_eq!(
+        r.get("color"),
+        Some(&Value::Enum(Rule::Singular(Enum { number: 5 })))
+    );
+    r.set("color", Value::Enum(Rule::Singular(Enum { number: 6 })))
+        .unwrap();
+    let m = r.absorb().unwrap();
+    assert_eq!(m.color.value(), 6);
+
+    let mut out = Vec::new();
+    m.encode(&mut out);
+    assert_eq!(out, [0x40, 0x06]);
+}
+
+#[test]
+fn moves_unknown_closed_enum_values_to_unknown_fields() {
+    // kind = 7, which ComplexEnum does not define.
+    let buf = [0x20, 0x07];
+    let m = Item::decode(&buf).unwrap();
+    assert_eq!(m.kind, None);
+    assert_eq!(m.unknown_fields, buf);
+
+    let mut out = Vec::new();
+    m.encode(&mut out);
+    assert_eq!(out, buf);
+}
+
+// Knows only the name of an Item.
+#[derive(Debug, Clone, Default, PartialEq, Reflect)]
+struct Name {
+    #[proto(number = 1, ty = "string")]
+    name: String,
+    #[proto(unknown_fields)]
+    unknown_fields: Vec<u8>,
+}
+
+// Field 8 as a closed enum.
+#[derive(Debug, Clone, Default, PartialEq, Reflect)]
+struct ClosedColor {
+    #[proto(number = 8, ty = "enum")]
+    color: EnumOrUnknown<ComplexEnum>,
+}
+
+// Field 3 as a singular value.
+#[derive(Debug, Clone, Default, PartialEq, Reflect)]
+struct SingleId {
+    #[proto(number = 3, ty = "int32")]
+    ids: i32,
+}
+
+// Field 2 with explicit presence.
+#[derive(Debug, Clone, Default, PartialEq, Reflect)]
+struct OptionalDelta {
+    #[proto(number = 2, ty = "sint32")]
+    delta: Option<i32>,
+}
+
+#[test]
+fn retarget_round_trips_through_unknown_fields() {
+    let narrow = item().reflect().retarget::<Name>().unwrap();
+    assert_eq!(
+        narrow.get("name"),
+        Some(&Value::String(Rule::Singular("widget".to_owned())))
+    );
+    assert!(!narrow.message().unknown_fields.is_empty());
+
+    let back = narrow.retarget::<Item>().unwrap();
+    assert!(back.message().unknown_fields.is_empty());
+    assert_eq!(back.absorb().unwrap(), item());
+}
+
+#[test]
+fn retarget_rejects_incompatible_fields() {
+    let e = item().reflect().retarget::<ClosedColor>().unwrap_err();
+    assert!(matches!(e, AbsorbError::Incompatible { ref field, .. } if field == "color"));
+    let e = item().reflect().retarget::<SingleId>().unwrap_err();
+    assert!(matches!(e, AbsorbError::Incompatible { ref field, .. } if field == "ids"));
+    let e = item().reflect().retarget::<OptionalDelta>().unwrap_err();
+    assert!(matches!(e, AbsorbError::Incompatible { ref field, .. } if field == "delta"));
+}
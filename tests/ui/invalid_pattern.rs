@@ -0,0 +1,7 @@
+#[derive(Clone, Default, tobu_conversion::Reflect)]
+struct Message {
+    #[proto(number = 1, ty = "string", pattern = "[a-z")]
+    name: String,
+}
+
+fn main() {}
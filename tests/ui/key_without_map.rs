@@ -0,0 +1,7 @@
+#[derive(Clone, Default, tobu_conversion::Reflect)]
+struct Message {
+    #[proto(number = 1, ty = "string", key = "int32")]
+    names: Vec<String>,
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+#[derive(Clone, Default, tobu_conversion::Reflect)]
+struct Message {
+    #[proto(ty = "bool")]
+    flag: bool,
+}
+
+fn main() {}
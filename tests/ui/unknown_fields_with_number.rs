@@ -0,0 +1,7 @@
+#[derive(Clone, Default, tobu_conversion::Reflect)]
+struct Message {
+    #[proto(unknown_fields, number = 2)]
+    unknown_fields: Vec<u8>,
+}
+
+fn main() {}